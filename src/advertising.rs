@@ -0,0 +1,359 @@
+//! Parsing whole BLE advertising packets, as handed over by a scanner.
+//!
+//! A packet is a series of AD structures, each a length byte, a type byte and `length - 1` bytes
+//! of data. Combustion devices put their state in manufacturer specific data under their company
+//! ID, which is checked for first so that the many packets from other devices are turned away
+//! before anything is allocated.
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use deku::prelude::*;
+
+use crate::gauge::GaugeManufacturerSpecificData;
+use crate::{ManufacturerSpecificData, ProductType};
+
+#[cfg(test)]
+use crate::temperature::Temperature;
+#[cfg(test)]
+use crate::{BatteryStatus, Color, Hops, Mode, NetworkInformation, SerialNumber};
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Combustion Inc.'s Bluetooth SIG company identifier.
+pub const COMBUSTION_COMPANY_ID: u16 = 0x09c7;
+
+pub const AD_TYPE_FLAGS: u8 = 0x01;
+pub const AD_TYPE_INCOMPLETE_UUIDS_16: u8 = 0x02;
+pub const AD_TYPE_COMPLETE_UUIDS_16: u8 = 0x03;
+pub const AD_TYPE_INCOMPLETE_UUIDS_128: u8 = 0x06;
+pub const AD_TYPE_COMPLETE_UUIDS_128: u8 = 0x07;
+pub const AD_TYPE_SHORTENED_LOCAL_NAME: u8 = 0x08;
+pub const AD_TYPE_COMPLETE_LOCAL_NAME: u8 = 0x09;
+pub const AD_TYPE_MANUFACTURER_DATA: u8 = 0xff;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AdvertisementError {
+    /// An AD structure runs past the end of the packet.
+    Truncated { offset: usize },
+    /// A service UUID list isn't a whole number of UUIDs.
+    InvalidUuidList { ad_type: u8, length: usize },
+    /// There's no manufacturer data with Combustion's company ID.
+    NotCombustion,
+    /// The manufacturer data has Combustion's company ID but couldn't be parsed.
+    InvalidManufacturerData(DekuError),
+}
+
+impl fmt::Display for AdvertisementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvertisementError::Truncated { offset } => {
+                write!(f, "AD structure at offset {} is truncated", offset)
+            }
+            AdvertisementError::InvalidUuidList { ad_type, length } => write!(
+                f,
+                "service UUID list of type {:#04x} has invalid length {}",
+                ad_type, length
+            ),
+            AdvertisementError::NotCombustion => write!(f, "not a Combustion advertisement"),
+            AdvertisementError::InvalidManufacturerData(error) => {
+                write!(f, "invalid Combustion manufacturer data: {}", error)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AdStructure<'a> {
+    pub ad_type: u8,
+    pub data: &'a [u8],
+}
+
+/// Iterates over the AD structures in a packet, stopping at the first zero length, after which
+/// the rest of the packet is padding.
+#[derive(Debug, Clone)]
+pub struct AdStructures<'a> {
+    packet: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> AdStructures<'a> {
+    pub fn new(packet: &'a [u8]) -> Self {
+        Self { packet, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = Result<AdStructure<'a>, AdvertisementError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let length = *self.packet.get(offset)? as usize;
+        if length == 0 {
+            self.offset = self.packet.len();
+            return None;
+        }
+
+        let Some(structure) = self.packet.get(offset + 1..offset + 1 + length) else {
+            self.offset = self.packet.len();
+            return Some(Err(AdvertisementError::Truncated { offset }));
+        };
+        self.offset += 1 + length;
+        Some(Ok(AdStructure {
+            ad_type: structure[0],
+            data: &structure[1..],
+        }))
+    }
+}
+
+/// The manufacturer data with Combustion's company ID, company ID included. Malformed packets
+/// are treated as not having any.
+pub fn combustion_manufacturer_data(packet: &[u8]) -> Option<&[u8]> {
+    AdStructures::new(packet)
+        .map_while(Result::ok)
+        .find(|structure| {
+            structure.ad_type == AD_TYPE_MANUFACTURER_DATA
+                && structure.data.get(..2) == Some(&COMBUSTION_COMPANY_ID.to_le_bytes())
+        })
+        .map(|structure| structure.data)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LocalName {
+    Complete(String),
+    Shortened(String),
+}
+
+impl LocalName {
+    pub fn as_str(&self) -> &str {
+        match self {
+            LocalName::Complete(name) | LocalName::Shortened(name) => name,
+        }
+    }
+}
+
+/// Everything we understand from an advertising packet and its scan response.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct AdvertisingData {
+    pub flags: Option<u8>,
+    pub local_name: Option<LocalName>,
+    pub service_uuids_16: Vec<u16>,
+    pub service_uuids_128: Vec<u128>,
+    /// Company ID and the data following it.
+    pub manufacturer_data: Vec<(u16, Vec<u8>)>,
+}
+
+impl AdvertisingData {
+    pub fn parse(
+        advertising: &[u8],
+        scan_response: Option<&[u8]>,
+    ) -> Result<Self, AdvertisementError> {
+        let mut data = Self::default();
+        data.add_packet(advertising)?;
+        if let Some(scan_response) = scan_response {
+            data.add_packet(scan_response)?;
+        }
+        Ok(data)
+    }
+
+    fn add_packet(&mut self, packet: &[u8]) -> Result<(), AdvertisementError> {
+        for structure in AdStructures::new(packet) {
+            let AdStructure { ad_type, data } = structure?;
+            match ad_type {
+                AD_TYPE_FLAGS => self.flags = data.first().copied(),
+                AD_TYPE_INCOMPLETE_UUIDS_16 | AD_TYPE_COMPLETE_UUIDS_16 => {
+                    let uuids = uuid_chunks::<2>(ad_type, data)?;
+                    self.service_uuids_16
+                        .extend(uuids.map(|uuid| u16::from_le_bytes(*uuid)));
+                }
+                AD_TYPE_INCOMPLETE_UUIDS_128 | AD_TYPE_COMPLETE_UUIDS_128 => {
+                    let uuids = uuid_chunks::<16>(ad_type, data)?;
+                    self.service_uuids_128
+                        .extend(uuids.map(|uuid| u128::from_le_bytes(*uuid)));
+                }
+                AD_TYPE_COMPLETE_LOCAL_NAME => {
+                    self.local_name = Some(LocalName::Complete(
+                        String::from_utf8_lossy(data).into_owned(),
+                    ));
+                }
+                // A complete name is better than a shortened one.
+                AD_TYPE_SHORTENED_LOCAL_NAME
+                    if !matches!(self.local_name, Some(LocalName::Complete(_))) =>
+                {
+                    self.local_name = Some(LocalName::Shortened(
+                        String::from_utf8_lossy(data).into_owned(),
+                    ));
+                }
+                AD_TYPE_MANUFACTURER_DATA if data.len() >= 2 => {
+                    let company_id = u16::from_le_bytes([data[0], data[1]]);
+                    self.manufacturer_data
+                        .push((company_id, data[2..].to_vec()));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// UUIDs are sent least significant byte first.
+fn uuid_chunks<const N: usize>(
+    ad_type: u8,
+    data: &[u8],
+) -> Result<impl Iterator<Item = &[u8; N]>, AdvertisementError> {
+    let (uuids, rest) = data.as_chunks::<N>();
+    if !rest.is_empty() {
+        return Err(AdvertisementError::InvalidUuidList {
+            ad_type,
+            length: data.len(),
+        });
+    }
+    Ok(uuids.iter())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CombustionData {
+    /// A probe, or a MeatNet node repeating one.
+    Probe(ManufacturerSpecificData),
+    /// A gauge, or a MeatNet node repeating one.
+    Gauge(GaugeManufacturerSpecificData),
+}
+
+impl CombustionData {
+    /// Parse manufacturer data, company ID included.
+    pub fn parse(manufacturer_data: &[u8]) -> Result<Self, AdvertisementError> {
+        let result = match manufacturer_data.get(2) {
+            Some(product_type) if *product_type == ProductType::Gauge as u8 => {
+                GaugeManufacturerSpecificData::from_bytes((manufacturer_data, 0))
+                    .map(|(_, data)| CombustionData::Gauge(data))
+            }
+            _ => ManufacturerSpecificData::from_bytes((manufacturer_data, 0))
+                .map(|(_, data)| CombustionData::Probe(data)),
+        };
+        result.map_err(AdvertisementError::InvalidManufacturerData)
+    }
+
+    pub fn product_type(&self) -> ProductType {
+        match self {
+            CombustionData::Probe(data) => data.product_type,
+            CombustionData::Gauge(data) => data.product_type,
+        }
+    }
+}
+
+/// An advertisement from a Combustion device.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CombustionAdvertisement {
+    pub data: CombustionData,
+    pub advertising_data: AdvertisingData,
+}
+
+impl CombustionAdvertisement {
+    /// Returns [`AdvertisementError::NotCombustion`] without parsing anything else if neither
+    /// packet has Combustion manufacturer data.
+    pub fn parse(
+        advertising: &[u8],
+        scan_response: Option<&[u8]>,
+    ) -> Result<Self, AdvertisementError> {
+        let manufacturer_data = combustion_manufacturer_data(advertising)
+            .or_else(|| scan_response.and_then(combustion_manufacturer_data))
+            .ok_or(AdvertisementError::NotCombustion)?;
+
+        Ok(Self {
+            data: CombustionData::parse(manufacturer_data)?,
+            advertising_data: AdvertisingData::parse(advertising, scan_response)?,
+        })
+    }
+
+    pub fn probe(&self) -> Option<&ManufacturerSpecificData> {
+        match &self.data {
+            CombustionData::Probe(data) => Some(data),
+            CombustionData::Gauge(_) => None,
+        }
+    }
+
+    pub fn gauge(&self) -> Option<&GaugeManufacturerSpecificData> {
+        match &self.data {
+            CombustionData::Gauge(data) => Some(data),
+            CombustionData::Probe(_) => None,
+        }
+    }
+
+    pub fn local_name(&self) -> Option<&str> {
+        self.advertising_data
+            .local_name
+            .as_ref()
+            .map(LocalName::as_str)
+    }
+}
+
+#[test]
+fn test_parse_combustion_advertisement() {
+    let mut advertising = vec![0x02, 0x01, 0x06, 0x19, 0xff];
+    advertising.extend([
+        0xc7, 0x09, 0x02, 0xed, 0x1d, 0x00, 0x10, 0x5c, 0x03, 0x6d, 0xb8, 0x0d, 0xb7, 0x11, 0x37,
+        0xe2, 0xc6, 0xd9, 0xf8, 0x1a, 0x00, 0xc0, 0x00, 0x00,
+    ]);
+    let mut scan_response = vec![0x04, 0x08, b'C', b'P', b'N', 0x11, 0x07];
+    scan_response.extend(0x00000100_caab_3792_3d44_97ae51c1407a_u128.to_le_bytes());
+    scan_response.extend([0x03, 0x03, 0x0a, 0x18, 0x00, 0x00]);
+
+    let advertisement = CombustionAdvertisement::parse(&advertising, Some(&scan_response)).unwrap();
+    assert_eq!(
+        advertisement.probe(),
+        Some(&ManufacturerSpecificData {
+            product_type: ProductType::MeatNetRepeater,
+            probe_serial_number: SerialNumber { number: 0x10001ded },
+            temperatures: [860, 872, 878, 878, 881, 881, 871, 863].map(Temperature::new),
+            probe_id: 0,
+            color: Color::Yellow,
+            mode: Mode::Normal,
+            virtual_ambient_sensor: 3,
+            virtual_surface_sensor: 0,
+            virtual_core_sensor: 0,
+            battery_status: BatteryStatus::Ok,
+            network_information: Some(NetworkInformation {
+                hop_count: Hops::One
+            }),
+        })
+    );
+    assert_eq!(advertisement.local_name(), Some("CPN"));
+    assert_eq!(advertisement.advertising_data.flags, Some(0x06));
+    assert_eq!(
+        advertisement.advertising_data.service_uuids_128,
+        vec![0x00000100_caab_3792_3d44_97ae51c1407a]
+    );
+    assert_eq!(
+        advertisement.advertising_data.service_uuids_16,
+        vec![0x180a]
+    );
+}
+
+#[test]
+fn test_rejects_other_advertisements() {
+    // Apple's company ID.
+    let advertising = [0x02, 0x01, 0x06, 0x05, 0xff, 0x4c, 0x00, 0x02, 0x15];
+    assert_eq!(
+        CombustionAdvertisement::parse(&advertising, None),
+        Err(AdvertisementError::NotCombustion)
+    );
+
+    let truncated = [0x02, 0x01, 0x06, 0x09, 0xff, 0xc7, 0x09];
+    assert_eq!(
+        CombustionAdvertisement::parse(&truncated, None),
+        Err(AdvertisementError::NotCombustion)
+    );
+    assert_eq!(
+        AdvertisingData::parse(&truncated, None),
+        Err(AdvertisementError::Truncated { offset: 3 })
+    );
+
+    let gauge = [0x05, 0xff, 0xc7, 0x09, 0x03, 0x47];
+    assert!(matches!(
+        CombustionAdvertisement::parse(&gauge, None),
+        Err(AdvertisementError::InvalidManufacturerData(_))
+    ));
+}
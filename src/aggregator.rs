@@ -0,0 +1,421 @@
+//! Combines everything we hear about each probe into one current state.
+//!
+//! A probe can be seen directly in its own advertisements and status notifications, and
+//! indirectly through every MeatNet node that repeats it. Newer data always wins; between
+//! readings of the same age, the one that took the fewest hops is kept.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+use crate::temperature::Temperature;
+use crate::uart::node::request::ProbeStatusMessage;
+use crate::{
+    BatteryStatus, Color, Hops, ManufacturerSpecificData, Mode, ProbeReadings, ProbeStatus,
+    ProductType, SerialNumber,
+};
+
+#[cfg(test)]
+use crate::{test_status, NetworkInformation};
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Statuses relayed along slower routes can arrive a few samples behind ones already seen. A log
+/// end further back than this means the probe has started a new session.
+const MAX_LOG_END_REORDERING: u32 = 8;
+
+/// Where a probe's state was last updated from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProbeSource {
+    /// The probe's own advertisement.
+    Advertisement,
+    /// A MeatNet node's advertisement repeating the probe.
+    RepeatedAdvertisement { hop_count: Hops },
+    /// A status notification from a direct connection to the probe.
+    Status,
+    /// A status message received over a node's UART.
+    NodeStatus { hop_count: Hops },
+}
+
+impl ProbeSource {
+    /// Number of MeatNet hops between the probe and us, 0 for data straight from the probe.
+    pub fn hops(&self) -> u8 {
+        match self {
+            ProbeSource::Advertisement | ProbeSource::Status => 0,
+            ProbeSource::RepeatedAdvertisement { hop_count }
+            | ProbeSource::NodeStatus { hop_count } => hop_count.count(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProbeState {
+    pub serial_number: SerialNumber,
+    pub temperatures: [Temperature; 8],
+    pub probe_id: u8,
+    pub color: Color,
+    pub mode: Mode,
+    pub virtual_core_sensor: u8,
+    pub virtual_surface_sensor: u8,
+    pub virtual_ambient_sensor: u8,
+    pub battery_status: BatteryStatus,
+    /// Only sent in status messages, so this is the most recent range seen even if the latest
+    /// update came from an advertisement.
+    pub log_range: Option<(u32, u32)>,
+    pub source: ProbeSource,
+    pub updated_at: Duration,
+    /// When the probe was first seen in its current Instant Read session, or `None` if it's
+    /// not in Instant Read mode.
+    pub instant_read_since: Option<Duration>,
+}
+
+impl ProbeState {
+    pub fn get_core_temperature(&self) -> &Temperature {
+        &self.temperatures[self.virtual_core_sensor as usize]
+    }
+
+    pub fn get_surface_temperature(&self) -> &Temperature {
+        &self.temperatures[self.virtual_surface_sensor as usize + 3]
+    }
+
+    pub fn get_ambient_temperature(&self) -> &Temperature {
+        &self.temperatures[self.virtual_ambient_sensor as usize + 4]
+    }
+}
+
+impl ProbeReadings for ProbeState {
+    fn get_temperatures(&self) -> &[Temperature; 8] {
+        &self.temperatures
+    }
+
+    fn get_mode(&self) -> Option<Mode> {
+        Some(self.mode)
+    }
+
+    fn get_virtual_core_sensor(&self) -> u8 {
+        self.virtual_core_sensor
+    }
+
+    fn get_virtual_surface_sensor(&self) -> u8 {
+        self.virtual_surface_sensor
+    }
+
+    fn get_virtual_ambient_sensor(&self) -> u8 {
+        self.virtual_ambient_sensor
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProbeAggregator {
+    freshness_window: Duration,
+    probes: BTreeMap<SerialNumber, ProbeState>,
+}
+
+impl ProbeAggregator {
+    /// Readings within `freshness_window` of each other are treated as the same age, so only
+    /// one with fewer hops can replace the current state.
+    pub fn new(freshness_window: Duration) -> Self {
+        Self {
+            freshness_window,
+            probes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether the advertisement updated the probe's state. Advertisements from gauges
+    /// aren't parsed as `ManufacturerSpecificData` so everything here is a probe or a node
+    /// repeating one.
+    pub fn ingest_advertisement(
+        &mut self,
+        advertisement: &ManufacturerSpecificData,
+        now: Duration,
+    ) -> bool {
+        let source = match (
+            advertisement.product_type,
+            &advertisement.network_information,
+        ) {
+            (ProductType::PredictiveProbe, _) => ProbeSource::Advertisement,
+            (_, Some(network_information)) => ProbeSource::RepeatedAdvertisement {
+                hop_count: network_information.hop_count,
+            },
+            _ => return false,
+        };
+
+        self.ingest(
+            ProbeState {
+                serial_number: advertisement.probe_serial_number,
+                temperatures: advertisement.temperatures,
+                probe_id: advertisement.probe_id,
+                color: advertisement.color,
+                mode: advertisement.mode,
+                virtual_core_sensor: advertisement.virtual_core_sensor,
+                virtual_surface_sensor: advertisement.virtual_surface_sensor,
+                virtual_ambient_sensor: advertisement.virtual_ambient_sensor,
+                battery_status: advertisement.battery_status,
+                log_range: None,
+                source,
+                updated_at: now,
+                instant_read_since: None,
+            },
+            now,
+        )
+    }
+
+    /// Returns whether the status updated the probe's state.
+    pub fn ingest_status(
+        &mut self,
+        serial_number: SerialNumber,
+        status: &ProbeStatus,
+        now: Duration,
+    ) -> bool {
+        self.ingest(
+            state_from_status(serial_number, status, ProbeSource::Status, now),
+            now,
+        )
+    }
+
+    /// Returns whether the status message updated the probe's state.
+    pub fn ingest_status_message(&mut self, message: &ProbeStatusMessage, now: Duration) -> bool {
+        self.ingest(
+            state_from_status(
+                message.probe_serial_number,
+                &message.status,
+                ProbeSource::NodeStatus {
+                    hop_count: message.network_information.hop_count,
+                },
+                now,
+            ),
+            now,
+        )
+    }
+
+    pub fn get(&self, serial_number: &SerialNumber) -> Option<&ProbeState> {
+        self.probes.get(serial_number)
+    }
+
+    pub fn probes(&self) -> impl Iterator<Item = &ProbeState> {
+        self.probes.values()
+    }
+
+    pub fn remove(&mut self, serial_number: &SerialNumber) -> Option<ProbeState> {
+        self.probes.remove(serial_number)
+    }
+
+    fn ingest(&mut self, mut candidate: ProbeState, now: Duration) -> bool {
+        let current = self.probes.get(&candidate.serial_number);
+        candidate.instant_read_since = match candidate.mode {
+            Mode::InstantRead => current
+                .and_then(|current| current.instant_read_since)
+                .or(Some(now)),
+            _ => None,
+        };
+        let Some(current) = current else {
+            self.probes.insert(candidate.serial_number, candidate);
+            return true;
+        };
+
+        let stale = now.saturating_sub(current.updated_at) > self.freshness_window;
+        let replace = match (candidate.log_range, current.log_range) {
+            (Some((_, candidate_end)), Some((_, current_end))) if candidate_end != current_end => {
+                stale
+                    || candidate_end > current_end
+                    || current_end - candidate_end > MAX_LOG_END_REORDERING
+            }
+            // The same reading again, e.g. repeated by another node.
+            (Some(_), Some(_)) => stale || candidate.source.hops() < current.source.hops(),
+            _ => stale || candidate.source.hops() <= current.source.hops(),
+        };
+
+        if replace {
+            candidate.log_range = candidate.log_range.or(current.log_range);
+            self.probes.insert(candidate.serial_number, candidate);
+        }
+        replace
+    }
+}
+
+fn state_from_status(
+    serial_number: SerialNumber,
+    status: &ProbeStatus,
+    source: ProbeSource,
+    now: Duration,
+) -> ProbeState {
+    ProbeState {
+        serial_number,
+        temperatures: status.temperatures,
+        probe_id: status.probe_id,
+        color: status.color,
+        mode: status.mode,
+        virtual_core_sensor: status.virtual_core_sensor,
+        virtual_surface_sensor: status.virtual_surface_sensor,
+        virtual_ambient_sensor: status.virtual_ambient_sensor,
+        battery_status: status.battery_status,
+        log_range: Some((status.log_start, status.log_end)),
+        source,
+        updated_at: now,
+        instant_read_since: None,
+    }
+}
+
+#[cfg(test)]
+fn test_advertisement(product_type: ProductType, hop_count: Hops) -> ManufacturerSpecificData {
+    ManufacturerSpecificData {
+        product_type,
+        probe_serial_number: SerialNumber { number: 0x10001ded },
+        temperatures: [Temperature::new(860); 8],
+        probe_id: 0,
+        color: Color::Yellow,
+        mode: Mode::Normal,
+        virtual_ambient_sensor: 3,
+        virtual_surface_sensor: 0,
+        virtual_core_sensor: 0,
+        battery_status: BatteryStatus::Ok,
+        network_information: product_type
+            .is_meatnet_node()
+            .then_some(NetworkInformation { hop_count }),
+    }
+}
+
+#[cfg(test)]
+fn test_status_message(log_end: u32, hop_count: Hops) -> ProbeStatusMessage {
+    ProbeStatusMessage {
+        probe_serial_number: SerialNumber { number: 0x10001ded },
+        status: ProbeStatus {
+            log_end,
+            ..test_status([Temperature::new(900); 8])
+        },
+        network_information: NetworkInformation { hop_count },
+    }
+}
+
+#[test]
+fn test_prefers_fewest_hops() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut aggregator = ProbeAggregator::new(Duration::from_secs(5));
+
+    assert!(aggregator.ingest_advertisement(
+        &test_advertisement(ProductType::MeatNetRepeater, Hops::Two),
+        Duration::from_secs(0),
+    ));
+    assert!(aggregator.ingest_advertisement(
+        &test_advertisement(ProductType::PredictiveProbe, Hops::One),
+        Duration::from_secs(1),
+    ));
+    assert!(!aggregator.ingest_advertisement(
+        &test_advertisement(ProductType::MeatNetRepeater, Hops::One),
+        Duration::from_secs(2),
+    ));
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().source,
+        ProbeSource::Advertisement
+    );
+
+    // Once the direct data is stale, the repeated data is used instead.
+    assert!(aggregator.ingest_advertisement(
+        &test_advertisement(ProductType::MeatNetRepeater, Hops::Three),
+        Duration::from_secs(7),
+    ));
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().source,
+        ProbeSource::RepeatedAdvertisement {
+            hop_count: Hops::Three
+        }
+    );
+    assert_eq!(aggregator.get(&serial_number).unwrap().source.hops(), 3);
+}
+
+#[test]
+fn test_newer_log_wins() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut aggregator = ProbeAggregator::new(Duration::from_secs(5));
+
+    assert!(aggregator.ingest_status_message(&test_status_message(10, Hops::One), Duration::ZERO));
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(11, Hops::Four), Duration::from_secs(1)));
+    // A duplicate of the same reading through a longer route is ignored.
+    assert!(!aggregator
+        .ingest_status_message(&test_status_message(11, Hops::Four), Duration::from_secs(1)));
+    assert!(!aggregator
+        .ingest_status_message(&test_status_message(10, Hops::One), Duration::from_secs(2)));
+
+    let state = aggregator.get(&serial_number).unwrap();
+    assert_eq!(state.log_range, Some((0, 11)));
+    assert_eq!(
+        state.source,
+        ProbeSource::NodeStatus {
+            hop_count: Hops::Four
+        }
+    );
+
+    // Advertisements don't carry the log range, so it's kept from the last status.
+    assert!(aggregator.ingest_advertisement(
+        &test_advertisement(ProductType::PredictiveProbe, Hops::One),
+        Duration::from_secs(2),
+    ));
+    let state = aggregator.get(&serial_number).unwrap();
+    assert_eq!(state.source, ProbeSource::Advertisement);
+    assert_eq!(state.log_range, Some((0, 11)));
+    assert_eq!(*state.get_core_temperature(), Temperature::new(860));
+}
+
+#[test]
+fn test_new_session_after_reset() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut aggregator = ProbeAggregator::new(Duration::from_secs(5));
+
+    assert!(aggregator.ingest_status_message(&test_status_message(500, Hops::One), Duration::ZERO));
+    // The probe was reset, so its log starts again.
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(2, Hops::One), Duration::from_secs(1)));
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(3, Hops::One), Duration::from_secs(2)));
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().log_range,
+        Some((0, 3))
+    );
+
+    // A reset soon after the last one looks like a late reading until the state goes stale.
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(10, Hops::One), Duration::from_secs(3)));
+    assert!(!aggregator
+        .ingest_status_message(&test_status_message(5, Hops::One), Duration::from_secs(4)));
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(5, Hops::One), Duration::from_secs(9)));
+    assert!(aggregator
+        .ingest_status_message(&test_status_message(6, Hops::One), Duration::from_secs(10)));
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().log_range,
+        Some((0, 6))
+    );
+}
+
+#[test]
+fn test_instant_read_sessions() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut aggregator = ProbeAggregator::new(Duration::from_secs(5));
+    let mut advertisement = test_advertisement(ProductType::PredictiveProbe, Hops::One);
+
+    aggregator.ingest_advertisement(&advertisement, Duration::ZERO);
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().instant_read_since,
+        None
+    );
+
+    advertisement.mode = Mode::InstantRead;
+    aggregator.ingest_advertisement(&advertisement, Duration::from_secs(1));
+    aggregator.ingest_advertisement(&advertisement, Duration::from_secs(2));
+    let state = aggregator.get(&serial_number).unwrap();
+    assert_eq!(state.instant_read_since, Some(Duration::from_secs(1)));
+    assert_eq!(
+        state.get_instant_read_temperature(),
+        Some(Temperature::new(860))
+    );
+    assert_eq!(state.get_valid_core_temperature(), None);
+
+    advertisement.mode = Mode::Normal;
+    aggregator.ingest_advertisement(&advertisement, Duration::from_secs(3));
+    assert_eq!(
+        aggregator.get(&serial_number).unwrap().instant_read_since,
+        None
+    );
+}
@@ -0,0 +1,6 @@
+//! Working things out from probe readings on our side, rather than relying on the probe.
+
+pub mod placement;
+pub mod prediction;
+pub mod resting;
+pub mod virtual_sensors;
@@ -0,0 +1,360 @@
+//! Working out where the probe sits in the food from the spread of temperatures along it.
+//!
+//! Once cooking starts, the sensors in the air quickly read far hotter than the ones in the
+//! food, so there's a sharp step in temperature at the surface of the food. Where that step
+//! falls along the shaft gives the insertion depth. With no sharp step but temperatures rising
+//! steadily from the coolest point towards the handle, the handle is in the food too. Anything
+//! else can't be read either way.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::temperature::{IsTemperature, Temperature};
+use crate::uart::node::response::ReadLogs;
+use crate::{Mode, ProbeReadings, ProbeStatus, SENSOR_POSITIONS_MM};
+
+#[cfg(test)]
+use crate::sim::heat::{Environment, HeatModel, Meat};
+#[cfg(test)]
+use crate::test_status;
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Below this spread between the hottest and coolest sensors, in °C, cooking hasn't started and
+/// there's nothing to go on.
+const MINIMUM_SPREAD: f32 = 5.0;
+/// The step at the surface of the food has to account for at least this much of the spread...
+const SURFACE_STEP_FRACTION: f32 = 0.5;
+/// ...and be at least this big, in °C. Cooking air is far hotter than food that's just started
+/// cooking, while a buried handle only warms gradually.
+const MINIMUM_SURFACE_STEP: f32 = 30.0;
+/// How much warmer than the coolest sensor the tip can be while still counting as in the
+/// thickest part, in °C.
+const TIP_TOLERANCE: f32 = 2.0;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PlacementWarning {
+    /// The handle's sensor is in the food, so ambient readings are wrong and the handle may get
+    /// too hot.
+    HandleBuried,
+    /// The coolest point is away from the tip, so the tip has gone past the thickest part or
+    /// isn't in it.
+    TipNotInThickestPart { coolest_sensor: u8 },
+    /// Only the tip sensor is in the food.
+    Shallow,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Insertion {
+    /// The tip is `depth_mm` into the food, with `sensors_in_food` sensors, from T1, in it.
+    Partial { depth_mm: f32, sensors_in_food: u8 },
+    /// The whole probe, handle included, is in the food.
+    Full,
+    /// There's neither a step at the surface nor a gradient towards the handle to go on.
+    Unknown,
+}
+
+impl Insertion {
+    /// How many sensors, from T1, are in the food, if that's known.
+    pub fn sensors_in_food(&self) -> Option<u8> {
+        match self {
+            Insertion::Partial {
+                sensors_in_food, ..
+            } => Some(*sensors_in_food),
+            Insertion::Full => Some(8),
+            Insertion::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Placement {
+    pub insertion: Insertion,
+    pub warnings: Vec<PlacementWarning>,
+}
+
+impl Placement {
+    /// `None` until the readings are spread enough to tell anything. Sensors that aren't giving
+    /// real readings are left out.
+    pub fn estimate(temperatures: &[Temperature; 8]) -> Option<Self> {
+        let celsius = temperatures.map(|temperature| {
+            temperature
+                .valid()
+                .map(|temperature| temperature.get_celsius())
+        });
+        let valid = || celsius.iter().flatten().copied();
+        let coolest = valid().fold(f32::INFINITY, f32::min);
+        let hottest = valid().fold(f32::NEG_INFINITY, f32::max);
+        let spread = hottest - coolest;
+        if spread < MINIMUM_SPREAD {
+            return None;
+        }
+
+        // The biggest rise in temperature going from the tip towards the handle, between
+        // neighbouring sensors that both have readings.
+        let (mut edge, mut step) = (0, f32::NEG_INFINITY);
+        for i in 0..7 {
+            if let (Some(inner), Some(outer)) = (celsius[i], celsius[i + 1]) {
+                if outer - inner > step {
+                    (edge, step) = (i, outer - inner);
+                }
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let insertion = if step >= MINIMUM_SURFACE_STEP && step >= spread * SURFACE_STEP_FRACTION {
+            Insertion::Partial {
+                depth_mm: (SENSOR_POSITIONS_MM[edge] + SENSOR_POSITIONS_MM[edge + 1]) / 2.0,
+                sensors_in_food: edge as u8 + 1,
+            }
+        } else if rises_towards_handle(&celsius, coolest) {
+            warnings.push(PlacementWarning::HandleBuried);
+            Insertion::Full
+        } else {
+            Insertion::Unknown
+        };
+        let Some(sensors_in_food) = insertion.sensors_in_food() else {
+            return Some(Self {
+                insertion,
+                warnings,
+            });
+        };
+
+        if sensors_in_food == 1 {
+            warnings.push(PlacementWarning::Shallow);
+        }
+
+        // Without a reading at the tip there's nothing to compare against.
+        if let Some(tip) = celsius[0] {
+            let (mut coolest_sensor, mut coolest_in_food) = (0, tip);
+            for (i, temperature) in celsius.iter().enumerate().take(sensors_in_food as usize) {
+                if let Some(temperature) = *temperature {
+                    if temperature < coolest_in_food {
+                        (coolest_sensor, coolest_in_food) = (i, temperature);
+                    }
+                }
+            }
+            if tip - coolest_in_food > TIP_TOLERANCE {
+                warnings.push(PlacementWarning::TipNotInThickestPart {
+                    coolest_sensor: coolest_sensor as u8,
+                });
+            }
+        }
+
+        Some(Self {
+            insertion,
+            warnings,
+        })
+    }
+
+    /// `None` if the probe is errored or in instant read, where it's being moved about and only
+    /// the tip matters.
+    pub fn from_readings(readings: &impl ProbeReadings) -> Option<Self> {
+        if matches!(readings.get_mode(), Some(Mode::Errored | Mode::InstantRead)) {
+            return None;
+        }
+        Self::estimate(readings.get_temperatures())
+    }
+
+    pub fn from_status(status: &ProbeStatus) -> Option<Self> {
+        Self::from_readings(status)
+    }
+
+    pub fn from_read_logs(read_logs: &ReadLogs) -> Option<Self> {
+        Self::from_readings(read_logs)
+    }
+}
+
+/// Whether the readings climb from the coolest sensor all the way to the handle, as they do when
+/// the handle is in the food and heat is working in from the surface around it.
+fn rises_towards_handle(celsius: &[Option<f32>; 8], coolest: f32) -> bool {
+    let Some(handle) = celsius[7] else {
+        return false;
+    };
+    if handle - coolest < MINIMUM_SPREAD {
+        return false;
+    }
+    let from_coolest = celsius
+        .iter()
+        .position(|temperature| *temperature == Some(coolest))
+        .unwrap_or(0);
+    let mut previous = coolest;
+    for temperature in celsius[from_coolest..].iter().flatten() {
+        if *temperature < previous - TIP_TOLERANCE {
+            return false;
+        }
+        previous = previous.max(*temperature);
+    }
+    true
+}
+
+/// Checks placement while a cook is getting going and reports each problem once.
+#[derive(Debug, Clone)]
+pub struct PlacementMonitor {
+    window: Duration,
+    started_at: Option<Duration>,
+    placement: Option<Placement>,
+    reported: Vec<PlacementWarning>,
+}
+
+impl PlacementMonitor {
+    /// Warnings are given for `window` after placement can first be estimated. After that the
+    /// probe is likely to be where it's staying, and readings change as the food cooks through.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            started_at: None,
+            placement: None,
+            reported: Vec::new(),
+        }
+    }
+
+    /// Returns any warnings that haven't been given before. Readings from an errored probe or
+    /// one in instant read are ignored, and don't start the window.
+    pub fn update(
+        &mut self,
+        readings: &impl ProbeReadings,
+        now: Duration,
+    ) -> Vec<PlacementWarning> {
+        if self
+            .started_at
+            .is_some_and(|started_at| now.saturating_sub(started_at) > self.window)
+        {
+            return Vec::new();
+        }
+        let Some(placement) = Placement::from_readings(readings) else {
+            return Vec::new();
+        };
+        self.started_at.get_or_insert(now);
+
+        let new: Vec<_> = placement
+            .warnings
+            .iter()
+            .filter(|warning| !self.reported.contains(warning))
+            .copied()
+            .collect();
+        self.reported.extend(&new);
+        self.placement = Some(placement);
+        new
+    }
+
+    /// The most recent estimate from the start of the cook.
+    pub fn placement(&self) -> Option<&Placement> {
+        self.placement.as_ref()
+    }
+}
+
+#[cfg(test)]
+fn placement_after(radius_mm: f64, insertion_depth_mm: f64) -> Option<Placement> {
+    let mut model = HeatModel::new(
+        Meat::beef(radius_mm),
+        Environment::oven(160.0),
+        insertion_depth_mm,
+    );
+    model.advance_to(Duration::from_secs(20 * 60));
+    Placement::estimate(&model.sensor_temperatures())
+}
+
+#[test]
+fn test_placement_from_simulated_cooks() {
+    // Tip in the centre.
+    let placement = placement_after(30.0, 33.0).unwrap();
+    assert_eq!(
+        placement.insertion,
+        Insertion::Partial {
+            depth_mm: 34.25,
+            sensors_in_food: 3
+        }
+    );
+    assert_eq!(placement.warnings, vec![]);
+
+    // Pushed most of the way through.
+    let placement = placement_after(30.0, 55.0).unwrap();
+    assert_eq!(placement.insertion.sensors_in_food(), Some(5));
+    assert_eq!(
+        placement.warnings,
+        vec![PlacementWarning::TipNotInThickestPart { coolest_sensor: 2 }]
+    );
+
+    // A big roast with the whole probe in it.
+    let placement = placement_after(80.0, 130.0).unwrap();
+    assert_eq!(placement.insertion, Insertion::Full);
+    assert!(placement.warnings.contains(&PlacementWarning::HandleBuried));
+}
+
+#[test]
+fn test_placement_unknown_without_gradient() {
+    // Spread out, but with no step and the handle cooler than the middle of the probe.
+    let temperatures = [480, 900, 1300, 1700, 1500, 1100, 800, 700].map(Temperature::new);
+    assert_eq!(
+        Placement::estimate(&temperatures),
+        Some(Placement {
+            insertion: Insertion::Unknown,
+            warnings: vec![],
+        })
+    );
+}
+
+#[test]
+fn test_placement_from_status_skips_instant_read() {
+    let mut status =
+        test_status([480, 3400, 3400, 3400, 3400, 3400, 3400, 3400].map(Temperature::new));
+    assert!(Placement::from_status(&status).is_some());
+
+    status.mode = Mode::InstantRead;
+    assert_eq!(Placement::from_status(&status), None);
+}
+
+#[test]
+fn test_placement_monitor_reports_once() {
+    let mut monitor = PlacementMonitor::new(Duration::from_secs(300));
+    let cold = test_status([Temperature::new(480); 8]);
+    let shallow =
+        test_status([480, 3400, 3400, 3400, 3400, 3400, 3400, 3400].map(Temperature::new));
+
+    assert_eq!(monitor.update(&cold, Duration::ZERO), vec![]);
+    assert_eq!(
+        monitor.update(&shallow, Duration::from_secs(5)),
+        vec![PlacementWarning::Shallow]
+    );
+    assert_eq!(monitor.update(&shallow, Duration::from_secs(10)), vec![]);
+    assert_eq!(
+        monitor.placement().unwrap().insertion.sensors_in_food(),
+        Some(1)
+    );
+}
+
+#[test]
+fn test_placement_skips_invalid_sensors() {
+    // T3 is disconnected, and would otherwise look like the coolest point.
+    let temperatures = [480, 470, 0, 460, 3400, 3400, 3400, 3400].map(Temperature::new);
+    let placement = Placement::estimate(&temperatures).unwrap();
+    assert_eq!(placement.insertion.sensors_in_food(), Some(4));
+    assert_eq!(placement.warnings, vec![]);
+
+    let mut temperatures = [Temperature::new(Temperature::MAX_RAW); 8];
+    temperatures[0] = Temperature::new(480);
+    assert_eq!(Placement::estimate(&temperatures), None);
+}
+
+#[test]
+fn test_placement_monitor_ignores_instant_read() {
+    let mut monitor = PlacementMonitor::new(Duration::from_secs(300));
+    let mut shallow =
+        test_status([480, 3400, 3400, 3400, 3400, 3400, 3400, 3400].map(Temperature::new));
+
+    // Being waved about in instant read gives no warnings, and doesn't start the window.
+    shallow.mode = Mode::InstantRead;
+    assert_eq!(monitor.update(&shallow, Duration::ZERO), vec![]);
+    assert_eq!(monitor.placement(), None);
+
+    shallow.mode = Mode::Normal;
+    assert_eq!(
+        monitor.update(&shallow, Duration::from_secs(600)),
+        vec![PlacementWarning::Shallow]
+    );
+}
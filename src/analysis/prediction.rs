@@ -0,0 +1,324 @@
+//! Estimating when the core will reach a set point, without relying on the probe's own
+//! prediction.
+//!
+//! Heat is modelled as flowing from the ambient air to the surface and from the surface to the
+//! core, each at a rate proportional to the temperature difference, as in Newton's law of
+//! heating. The two rate constants are fitted to the samples seen so far, weighting recent ones
+//! more heavily, and the model is run forward to find when the core gets to the set point. The
+//! uncertainty in the core's rate constant gives the confidence interval.
+
+use core::time::Duration;
+
+use crate::temperature::IsTemperature;
+use crate::uart::node::response::ReadLogs;
+use crate::{ProbeReadings, ProbeStatus};
+
+#[cfg(test)]
+use crate::sim::heat::{Environment, HeatModel, Meat};
+#[cfg(test)]
+use crate::sim::probe::{VirtualProbe, VirtualProbeConfig};
+#[cfg(test)]
+use crate::SerialNumber;
+
+/// Roughly a 95% confidence interval.
+const CONFIDENCE_Z: f32 = 1.96;
+/// Weighted number of samples needed before estimating.
+const MINIMUM_SAMPLES: f32 = 10.0;
+/// Predictions further out than this are treated as never getting there.
+const HORIZON: Duration = Duration::from_secs(24 * 60 * 60);
+const FORGETTING_FACTOR: f32 = 0.99;
+/// The most steps taken running the model forward, however quickly it changes.
+const MAX_STEPS: u32 = 10_000;
+
+/// One reading of the virtual sensors, in °C.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sample {
+    /// Time since the start of the probe's session.
+    pub elapsed: Duration,
+    pub core: f32,
+    pub surface: f32,
+    pub ambient: f32,
+}
+
+impl Sample {
+    /// Log entries are timed by their sequence number. `None` if any of the virtual sensors
+    /// isn't giving a real reading.
+    pub fn from_read_logs(read_logs: &ReadLogs, sample_period: Duration) -> Option<Self> {
+        Some(Self {
+            elapsed: sample_period * read_logs.sequence_number,
+            core: read_logs.get_valid_core_temperature()?.get_celsius(),
+            surface: read_logs.get_valid_surface_temperature()?.get_celsius(),
+            ambient: read_logs.get_valid_ambient_temperature()?.get_celsius(),
+        })
+    }
+
+    /// A status is the reading for the newest log entry. `None` if the probe is errored or any
+    /// of the virtual sensors isn't giving a real reading.
+    pub fn from_status(status: &ProbeStatus, sample_period: Duration) -> Option<Self> {
+        Some(Self {
+            elapsed: sample_period * status.log_end,
+            core: status.get_valid_core_temperature()?.get_celsius(),
+            surface: status.get_valid_surface_temperature()?.get_celsius(),
+            ambient: status.get_valid_ambient_temperature()?.get_celsius(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Prediction {
+    /// Most likely time until the core reaches the set point.
+    pub remaining: Duration,
+    pub earliest: Duration,
+    /// `None` if, at the slow end of the interval, the core might never get there.
+    pub latest: Option<Duration>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Estimate {
+    /// Not enough samples of the core heating up yet.
+    NotEnoughData,
+    /// The core is already at or above the set point.
+    Reached,
+    /// At the current ambient temperature the core won't reach the set point.
+    Unreachable,
+    Remaining(Prediction),
+}
+
+/// Exponentially weighted least squares fit of `rate = k * difference`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RateFit {
+    sum_xx: f32,
+    sum_xy: f32,
+    sum_yy: f32,
+    weight: f32,
+}
+
+impl RateFit {
+    pub(crate) fn add(&mut self, difference: f32, rate: f32) {
+        self.sum_xx = FORGETTING_FACTOR * self.sum_xx + difference * difference;
+        self.sum_xy = FORGETTING_FACTOR * self.sum_xy + difference * rate;
+        self.sum_yy = FORGETTING_FACTOR * self.sum_yy + rate * rate;
+        self.weight = FORGETTING_FACTOR * self.weight + 1.0;
+    }
+
+    pub(crate) fn rate_constant(&self) -> Option<f32> {
+        (self.weight >= MINIMUM_SAMPLES && self.sum_xx > 0.0).then(|| self.sum_xy / self.sum_xx)
+    }
+
+    pub(crate) fn standard_error(&self, k: f32) -> f32 {
+        let residual = (self.sum_yy - k * self.sum_xy).max(0.0);
+        libm::sqrtf(residual / (self.weight - 1.0) / self.sum_xx)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RemovalPredictor {
+    set_point: f32,
+    last: Option<Sample>,
+    core: RateFit,
+    surface: RateFit,
+}
+
+impl RemovalPredictor {
+    /// `set_point` is the core temperature to remove at, in °C.
+    pub fn new(set_point: f32) -> Self {
+        Self {
+            set_point,
+            last: None,
+            core: RateFit::default(),
+            surface: RateFit::default(),
+        }
+    }
+
+    pub fn set_point(&self) -> f32 {
+        self.set_point
+    }
+
+    pub fn set_set_point(&mut self, set_point: f32) {
+        self.set_point = set_point;
+    }
+
+    /// Add the next sample and return the updated estimate. Samples that aren't newer than the
+    /// last one are ignored.
+    pub fn update(&mut self, sample: Sample) -> Estimate {
+        match self.last {
+            Some(last) if sample.elapsed <= last.elapsed => return self.estimate(),
+            Some(last) => {
+                let seconds = (sample.elapsed - last.elapsed).as_secs_f32();
+                self.core.add(
+                    last.surface - last.core,
+                    (sample.core - last.core) / seconds,
+                );
+                self.surface.add(
+                    last.ambient - last.surface,
+                    (sample.surface - last.surface) / seconds,
+                );
+            }
+            None => {}
+        }
+        self.last = Some(sample);
+        self.estimate()
+    }
+
+    /// The fitted rate constants per second for heat getting from the surface to the core and
+    /// from the ambient air to the surface, or `None` until there are enough samples.
+    pub fn rate_constants(&self) -> Option<(f32, f32)> {
+        Some((self.core.rate_constant()?, self.surface.rate_constant()?))
+    }
+
+    pub fn estimate(&self) -> Estimate {
+        let Some(last) = self.last else {
+            return Estimate::NotEnoughData;
+        };
+        if last.core >= self.set_point {
+            return Estimate::Reached;
+        }
+        if last.ambient <= self.set_point {
+            return Estimate::Unreachable;
+        }
+        let Some((core_k, surface_k)) = self.rate_constants() else {
+            return Estimate::NotEnoughData;
+        };
+        if core_k <= 0.0 {
+            return Estimate::NotEnoughData;
+        }
+
+        let standard_error = self.core.standard_error(core_k);
+        let time_for = |core_k: f32| {
+            (core_k > 0.0)
+                .then(|| time_to_set_point(last, self.set_point, core_k, surface_k.max(0.0)))
+                .flatten()
+        };
+
+        match time_for(core_k) {
+            Some(remaining) => Estimate::Remaining(Prediction {
+                remaining,
+                earliest: time_for(core_k + CONFIDENCE_Z * standard_error).unwrap_or(remaining),
+                latest: time_for(core_k - CONFIDENCE_Z * standard_error),
+            }),
+            None => Estimate::Unreachable,
+        }
+    }
+}
+
+/// Run the model forward from `from` for up to `horizon`, with the surface heading towards the
+/// ambient temperature and the core towards the surface. Yields the time since `from` in seconds
+/// and the core and surface temperatures after each step.
+///
+/// Each step uses the exact solution with the surface held at its average over the step, so it
+/// stays stable however large the steps have to be to reach the horizon in [`MAX_STEPS`].
+pub(crate) fn run_model(
+    from: Sample,
+    core_k: f32,
+    surface_k: f32,
+    horizon: Duration,
+) -> impl Iterator<Item = (f32, f32, f32)> {
+    let horizon = horizon.as_secs_f32();
+    // Small enough steps that each temperature moves only a little towards its target.
+    let step = (0.05 / core_k.max(surface_k))
+        .min(10.0)
+        .max(horizon / MAX_STEPS as f32);
+    let steps = libm::ceilf(horizon / step) as u32;
+    let core_decay = libm::expf(-core_k * step);
+    let surface_decay = libm::expf(-surface_k * step);
+
+    (1..=steps).scan((from.core, from.surface), move |(core, surface), n| {
+        let next_surface = from.ambient + (*surface - from.ambient) * surface_decay;
+        let average_surface = (*surface + next_surface) / 2.0;
+        *core = average_surface + (*core - average_surface) * core_decay;
+        *surface = next_surface;
+        Some((n as f32 * step, *core, *surface))
+    })
+}
+
+/// Run the model forward from `from` until the core reaches `set_point`, assuming the ambient
+/// temperature stays the same.
+fn time_to_set_point(
+    from: Sample,
+    set_point: f32,
+    core_k: f32,
+    surface_k: f32,
+) -> Option<Duration> {
+    let (mut last_elapsed, mut last_core) = (0.0, from.core);
+    if last_core >= set_point {
+        return Some(Duration::ZERO);
+    }
+    for (elapsed, core, _) in run_model(from, core_k, surface_k, HORIZON) {
+        if core >= set_point {
+            // Interpolate within the step.
+            let fraction = (set_point - last_core) / (core - last_core);
+            return Some(Duration::from_secs_f32(
+                last_elapsed + fraction * (elapsed - last_elapsed),
+            ));
+        }
+        (last_elapsed, last_core) = (elapsed, core);
+    }
+    None
+}
+
+#[test]
+fn test_predicts_simulated_cook() {
+    let sample_period = Duration::from_secs(5);
+    let model = HeatModel::new(Meat::beef(30.0), Environment::oven(160.0), 50.0);
+    let mut probe = VirtualProbe::new(
+        VirtualProbeConfig::new(SerialNumber { number: 1 }),
+        model,
+        Duration::ZERO,
+    );
+    let mut predictor = RemovalPredictor::new(55.0);
+    assert_eq!(predictor.estimate(), Estimate::NotEnoughData);
+
+    let mut predicted_at = None;
+    let mut reached_at = None;
+    for second in (0..4 * 60 * 60).step_by(5) {
+        let now = Duration::from_secs(second);
+        probe.handle_timeout(now);
+        let estimate =
+            predictor.update(Sample::from_status(&probe.status(), sample_period).unwrap());
+
+        // Take a prediction 20 minutes in, then see when the target is actually reached.
+        if now == Duration::from_secs(20 * 60) {
+            let Estimate::Remaining(prediction) = estimate else {
+                panic!("Expected a prediction, got {estimate:?}");
+            };
+            predicted_at = Some((now, prediction));
+        }
+        if estimate == Estimate::Reached {
+            reached_at = Some(now);
+            break;
+        }
+    }
+
+    let (now, prediction) = predicted_at.unwrap();
+    let actual = reached_at.unwrap() - now;
+    let error = actual.as_secs_f32() / prediction.remaining.as_secs_f32();
+    assert!(
+        (0.75..1.25).contains(&error),
+        "{prediction:?} vs {actual:?}"
+    );
+    assert!(prediction.earliest <= prediction.remaining);
+    assert!(prediction
+        .latest
+        .is_none_or(|latest| latest >= prediction.remaining));
+
+    predictor.set_set_point(300.0);
+    assert_eq!(predictor.estimate(), Estimate::Unreachable);
+}
+
+#[test]
+fn test_time_to_set_point_is_bounded() {
+    let from = Sample {
+        elapsed: Duration::ZERO,
+        core: 20.0,
+        surface: 60.0,
+        ambient: 150.0,
+    };
+
+    // Fast enough that stepping by a fraction of the time constant would never get anywhere.
+    // It's all over within the first step.
+    let remaining = time_to_set_point(from, 55.0, 50.0, 40.0).unwrap();
+    assert!(remaining < Duration::from_secs(10), "{remaining:?}");
+
+    // Far too slow to get there within the horizon.
+    assert_eq!(time_to_set_point(from, 55.0, 1e-9, 1e-9), None);
+}
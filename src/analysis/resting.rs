@@ -0,0 +1,347 @@
+//! Spotting when food comes off the heat and predicting how far the core will carry over.
+//!
+//! Removal shows up as the ambient temperature falling well below where it's been recently.
+//! While resting, the heat already between the surface and the core keeps flowing inwards, so
+//! the core keeps rising until the surface has cooled to meet it. That's predicted with the same
+//! surface-to-core rate as [`crate::analysis::prediction`] fits while cooking, and the surface's
+//! cooling rate fitted as it rests.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::analysis::prediction::{run_model, RateFit, Sample};
+
+#[cfg(test)]
+use crate::sim::heat::{Environment, Event, HeatModel, Meat};
+#[cfg(test)]
+use alloc::vec::Vec;
+
+/// Until the surface's cooling has been measured, it's assumed to lose heat this much slower than
+/// it gained it in the oven, roughly the ratio of still air's heat transfer to an oven's.
+const INITIAL_COOLING_RATIO: f32 = 0.5;
+/// How far the core has to fall from its highest point, in °C, before that counts as the peak.
+const PEAK_CONFIRMATION_DROP: f32 = 0.2;
+const HORIZON: Duration = Duration::from_secs(4 * 60 * 60);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RestingConfig {
+    /// How far the ambient temperature has to fall below its recent peak to count as removal,
+    /// in °C.
+    pub removal_drop: f32,
+    /// How far back the recent peak is looked for.
+    pub window: Duration,
+}
+
+impl Default for RestingConfig {
+    fn default() -> Self {
+        Self {
+            removal_drop: 25.0,
+            window: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Phase {
+    Cooking,
+    Resting,
+    /// The core has peaked and is cooling down. Putting it back on the heat starts over with
+    /// [`Phase::Cooking`].
+    Done,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PeakPrediction {
+    /// In °C.
+    pub temperature: f32,
+    /// Time since the start of the session.
+    pub at: Duration,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Carryover {
+    pub removed_at: Duration,
+    pub core_at_removal: f32,
+    /// What was predicted at the moment of removal.
+    pub initial_prediction: Option<PeakPrediction>,
+    /// The latest prediction, refined as the surface cools.
+    pub prediction: Option<PeakPrediction>,
+    /// The highest core temperature seen since removal.
+    pub observed_peak: PeakPrediction,
+}
+
+impl Carryover {
+    /// How much the core has risen since removal.
+    pub fn observed_rise(&self) -> f32 {
+        self.observed_peak.temperature - self.core_at_removal
+    }
+
+    /// How far the latest prediction is from what's been seen, in °C.
+    pub fn prediction_error(&self) -> Option<f32> {
+        self.prediction
+            .map(|prediction| prediction.temperature - self.observed_peak.temperature)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RestingTracker {
+    config: RestingConfig,
+    phase: Phase,
+    last: Option<Sample>,
+    recent_ambient: VecDeque<(Duration, f32)>,
+    core_fit: RateFit,
+    heating_fit: RateFit,
+    cooling_fit: RateFit,
+    carryover: Option<Carryover>,
+}
+
+impl RestingTracker {
+    pub fn new(config: RestingConfig) -> Self {
+        Self {
+            config,
+            phase: Phase::Cooking,
+            last: None,
+            recent_ambient: VecDeque::new(),
+            core_fit: RateFit::default(),
+            heating_fit: RateFit::default(),
+            cooling_fit: RateFit::default(),
+            carryover: None,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Everything known about the carryover, once removal has been detected.
+    pub fn carryover(&self) -> Option<&Carryover> {
+        self.carryover.as_ref()
+    }
+
+    /// Add the next sample. Samples that aren't newer than the last one are ignored.
+    pub fn update(&mut self, sample: Sample) -> Phase {
+        let Some(last) = self.last else {
+            self.last = Some(sample);
+            self.recent_ambient
+                .push_back((sample.elapsed, sample.ambient));
+            return self.phase;
+        };
+        if sample.elapsed <= last.elapsed {
+            return self.phase;
+        }
+        self.last = Some(sample);
+
+        let seconds = (sample.elapsed - last.elapsed).as_secs_f32();
+        self.core_fit.add(
+            last.surface - last.core,
+            (sample.core - last.core) / seconds,
+        );
+        let surface_fit = match self.phase {
+            Phase::Cooking => &mut self.heating_fit,
+            Phase::Resting | Phase::Done => &mut self.cooling_fit,
+        };
+        surface_fit.add(
+            last.ambient - last.surface,
+            (sample.surface - last.surface) / seconds,
+        );
+
+        if self.phase != Phase::Cooking && self.detect_return(sample) {
+            // Back on the heat, so it's a new cook.
+            *self = Self::new(self.config);
+            self.last = Some(sample);
+            self.recent_ambient
+                .push_back((sample.elapsed, sample.ambient));
+            return self.phase;
+        }
+        match self.phase {
+            Phase::Cooking => self.detect_removal(sample),
+            Phase::Resting => self.track_peak(sample),
+            Phase::Done => {}
+        }
+        self.phase
+    }
+
+    /// Whether the ambient temperature has risen as far above its recent low as it has to fall
+    /// to count as removal.
+    fn detect_return(&mut self, sample: Sample) -> bool {
+        let window = self.config.window;
+        self.recent_ambient
+            .retain(|(at, _)| sample.elapsed.saturating_sub(*at) <= window);
+        let recent_low = self
+            .recent_ambient
+            .iter()
+            .map(|(_, ambient)| *ambient)
+            .fold(sample.ambient, f32::min);
+        self.recent_ambient
+            .push_back((sample.elapsed, sample.ambient));
+        sample.ambient - recent_low >= self.config.removal_drop
+    }
+
+    fn detect_removal(&mut self, sample: Sample) {
+        let window = self.config.window;
+        self.recent_ambient
+            .retain(|(at, _)| sample.elapsed.saturating_sub(*at) <= window);
+        let recent_peak = self
+            .recent_ambient
+            .iter()
+            .map(|(_, ambient)| *ambient)
+            .fold(sample.ambient, f32::max);
+        self.recent_ambient
+            .push_back((sample.elapsed, sample.ambient));
+
+        if recent_peak - sample.ambient < self.config.removal_drop {
+            return;
+        }
+
+        self.phase = Phase::Resting;
+        self.recent_ambient.clear();
+        let prediction = self.predict_peak(sample);
+        self.carryover = Some(Carryover {
+            removed_at: sample.elapsed,
+            core_at_removal: sample.core,
+            initial_prediction: prediction,
+            prediction,
+            observed_peak: PeakPrediction {
+                temperature: sample.core,
+                at: sample.elapsed,
+            },
+        });
+    }
+
+    fn track_peak(&mut self, sample: Sample) {
+        let prediction = self.predict_peak(sample);
+        let Some(carryover) = &mut self.carryover else {
+            return;
+        };
+
+        if sample.core > carryover.observed_peak.temperature {
+            carryover.observed_peak = PeakPrediction {
+                temperature: sample.core,
+                at: sample.elapsed,
+            };
+        }
+        if carryover.observed_peak.temperature - sample.core >= PEAK_CONFIRMATION_DROP {
+            self.phase = Phase::Done;
+        } else {
+            carryover.prediction = prediction;
+        }
+    }
+
+    /// Run the model forward from `from` until the core stops rising.
+    fn predict_peak(&self, from: Sample) -> Option<PeakPrediction> {
+        let core_k = self.core_fit.rate_constant()?;
+        let cooling_k = match self.cooling_fit.rate_constant() {
+            Some(k) => k,
+            None => self.heating_fit.rate_constant()? * INITIAL_COOLING_RATIO,
+        };
+        if core_k <= 0.0 || cooling_k <= 0.0 {
+            return None;
+        }
+
+        let mut peak = (0.0, from.core);
+        if from.surface > from.core {
+            for (elapsed, core, surface) in run_model(from, core_k, cooling_k, HORIZON) {
+                peak = (elapsed, core);
+                if surface <= core {
+                    break;
+                }
+            }
+        }
+
+        Some(PeakPrediction {
+            temperature: peak.1,
+            at: from.elapsed + Duration::from_secs_f32(peak.0),
+        })
+    }
+}
+
+#[test]
+fn test_detects_removal_and_predicts_peak() {
+    let removed_at = Duration::from_secs(50 * 60);
+    let mut model = HeatModel::new(Meat::beef(30.0), Environment::oven(160.0), 30.0);
+    model.schedule(removed_at, Event::Environment(Environment::room(21.0)));
+
+    let mut tracker = RestingTracker::new(RestingConfig::default());
+    let mut highest = (0.0, Duration::ZERO);
+    for second in (0..2 * 60 * 60).step_by(5) {
+        let now = Duration::from_secs(second);
+        model.advance_to(now);
+        let phase = tracker.update(Sample {
+            elapsed: now,
+            core: model.center_temperature() as f32,
+            surface: model.temperature_at_depth(5.0) as f32,
+            ambient: model.environment().temperature as f32,
+        });
+
+        if model.center_temperature() as f32 > highest.0 {
+            highest = (model.center_temperature() as f32, now);
+        }
+        if now < removed_at {
+            assert_eq!(phase, Phase::Cooking);
+        }
+    }
+    assert_eq!(tracker.phase(), Phase::Done);
+
+    let carryover = tracker.carryover().unwrap();
+    assert_eq!(carryover.removed_at, removed_at);
+    assert_eq!(carryover.observed_peak.temperature, highest.0);
+    assert!(carryover.observed_rise() > 3.0);
+
+    let initial = carryover.initial_prediction.unwrap();
+    assert!(
+        (initial.temperature - highest.0).abs() < 3.0,
+        "{initial:?} vs {highest:?}"
+    );
+    assert!(carryover.prediction_error().unwrap().abs() < 1.0);
+}
+
+#[test]
+fn test_second_cook() {
+    let mut model = HeatModel::new(Meat::beef(30.0), Environment::oven(160.0), 30.0);
+    model.schedule(
+        Duration::from_secs(50 * 60),
+        Event::Environment(Environment::room(21.0)),
+    );
+    model.schedule(
+        Duration::from_secs(90 * 60),
+        Event::Environment(Environment::oven(160.0)),
+    );
+    model.schedule(
+        Duration::from_secs(110 * 60),
+        Event::Environment(Environment::room(21.0)),
+    );
+
+    let mut tracker = RestingTracker::new(RestingConfig::default());
+    let mut phases = Vec::new();
+    for second in (0..3 * 60 * 60).step_by(5) {
+        let now = Duration::from_secs(second);
+        model.advance_to(now);
+        let phase = tracker.update(Sample {
+            elapsed: now,
+            core: model.center_temperature() as f32,
+            surface: model.temperature_at_depth(5.0) as f32,
+            ambient: model.environment().temperature as f32,
+        });
+        if phases.last() != Some(&phase) {
+            phases.push(phase);
+        }
+    }
+
+    assert_eq!(
+        phases,
+        [
+            Phase::Cooking,
+            Phase::Resting,
+            Phase::Done,
+            Phase::Cooking,
+            Phase::Resting,
+            Phase::Done
+        ]
+    );
+    assert_eq!(
+        tracker.carryover().unwrap().removed_at,
+        Duration::from_secs(110 * 60)
+    );
+}
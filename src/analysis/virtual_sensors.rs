@@ -0,0 +1,201 @@
+//! Choosing the virtual core, surface and ambient sensors from the 8 readings ourselves, for
+//! data where the probe's choice isn't available or can't be trusted.
+//!
+//! Indexes follow the probe's conventions: the core is one of T1-T6, the surface one of T4-T7
+//! and the ambient one of T5-T8, each counted from the first sensor in its range.
+
+use crate::temperature::Temperature;
+use crate::uart::node::response::ReadLogs;
+use crate::{ManufacturerSpecificData, ProbeStatus};
+
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Below this step between neighbouring sensors (1°C), there's no clear edge of the food.
+const MINIMUM_EDGE: u16 = 20;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct VirtualSensors {
+    pub core: u8,
+    pub surface: u8,
+    pub ambient: u8,
+}
+
+impl Default for VirtualSensors {
+    /// What the probe uses before it's chosen anything.
+    fn default() -> Self {
+        Self {
+            core: 0,
+            surface: 0,
+            ambient: 3,
+        }
+    }
+}
+
+impl VirtualSensors {
+    /// The core is the coolest of T1-T6. The edge of the food is the biggest step in
+    /// temperature between neighbouring sensors from T4 onwards, with the surface the last
+    /// sensor before it and the ambient the hottest sensor after it. Sensors that aren't giving
+    /// real readings are never chosen.
+    pub fn select(temperatures: &[Temperature; 8]) -> Self {
+        let raw = temperatures.map(|temperature| {
+            temperature
+                .valid()
+                .map(|temperature| temperature.get_raw_value())
+        });
+
+        let core = (0..6)
+            .filter_map(|i| Some((i, raw[i]?)))
+            .min_by_key(|(_, raw)| *raw)
+            .map_or(0, |(i, _)| i);
+
+        // Ties go to the sensor nearest the tip.
+        let (mut edge, mut step) = (3, 0);
+        for i in 3..7 {
+            if let (Some(inner), Some(outer)) = (raw[i], raw[i + 1]) {
+                if outer.abs_diff(inner) > step {
+                    (edge, step) = (i, outer.abs_diff(inner));
+                }
+            }
+        }
+        if step < MINIMUM_EDGE {
+            return Self {
+                core: core as u8,
+                ..Self::default()
+            };
+        }
+        // Ties go to the sensor nearest the handle. There's at least the one just past the edge.
+        let ambient = (edge + 1..8)
+            .filter_map(|i| Some((i, raw[i]?)))
+            .max_by_key(|(_, raw)| *raw)
+            .map_or(edge + 1, |(i, _)| i);
+
+        Self {
+            core: core as u8,
+            surface: (edge - 3) as u8,
+            ambient: (ambient - 4) as u8,
+        }
+    }
+
+    pub fn from_status(status: &ProbeStatus) -> Self {
+        Self {
+            core: status.virtual_core_sensor,
+            surface: status.virtual_surface_sensor,
+            ambient: status.virtual_ambient_sensor,
+        }
+    }
+
+    pub fn from_advertisement(advertisement: &ManufacturerSpecificData) -> Self {
+        Self {
+            core: advertisement.virtual_core_sensor,
+            surface: advertisement.virtual_surface_sensor,
+            ambient: advertisement.virtual_ambient_sensor,
+        }
+    }
+
+    pub fn from_read_logs(read_logs: &ReadLogs) -> Self {
+        Self {
+            core: read_logs.virtual_core_sensor,
+            surface: read_logs.virtual_surface_sensor,
+            ambient: read_logs.virtual_ambient_sensor,
+        }
+    }
+
+    /// Index into the 8 temperatures of each sensor.
+    pub fn core_index(&self) -> usize {
+        self.core as usize
+    }
+
+    pub fn surface_index(&self) -> usize {
+        self.surface as usize + 3
+    }
+
+    pub fn ambient_index(&self) -> usize {
+        self.ambient as usize + 4
+    }
+}
+
+/// Where our selection differs from the probe's.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Disagreement {
+    pub device: VirtualSensors,
+    pub computed: VirtualSensors,
+}
+
+impl Disagreement {
+    pub fn core(&self) -> bool {
+        self.device.core != self.computed.core
+    }
+
+    pub fn surface(&self) -> bool {
+        self.device.surface != self.computed.surface
+    }
+
+    pub fn ambient(&self) -> bool {
+        self.device.ambient != self.computed.ambient
+    }
+}
+
+/// Compare the probe's selection to ours, returning `None` if they agree.
+pub fn check(device: VirtualSensors, temperatures: &[Temperature; 8]) -> Option<Disagreement> {
+    let computed = VirtualSensors::select(temperatures);
+    (computed != device).then_some(Disagreement { device, computed })
+}
+
+#[test]
+fn test_select_virtual_sensors() {
+    // Inserted up to T5, so T6-T8 are in the oven air.
+    let temperatures = [620, 560, 540, 600, 900, 3400, 3420, 3300].map(Temperature::new);
+    let selected = VirtualSensors::select(&temperatures);
+    assert_eq!(
+        selected,
+        VirtualSensors {
+            core: 2,
+            surface: 1,
+            ambient: 2,
+        }
+    );
+    assert_eq!(selected.surface_index(), 4);
+    assert_eq!(selected.ambient_index(), 6);
+
+    // Everything the same temperature, e.g. before cooking.
+    let temperatures = [Temperature::new(842); 8];
+    assert_eq!(
+        VirtualSensors::select(&temperatures),
+        VirtualSensors::default()
+    );
+}
+
+#[test]
+fn test_disagreement() {
+    let temperatures = [620, 560, 540, 600, 900, 3400, 3420, 3300].map(Temperature::new);
+    let device = VirtualSensors {
+        core: 2,
+        surface: 1,
+        ambient: 3,
+    };
+
+    let disagreement = check(device, &temperatures).unwrap();
+    assert!(!disagreement.core());
+    assert!(!disagreement.surface());
+    assert!(disagreement.ambient());
+
+    assert_eq!(
+        check(VirtualSensors::select(&temperatures), &temperatures),
+        None
+    );
+}
+
+#[test]
+fn test_select_skips_invalid_sensors() {
+    // T3 has failed low and T7 high.
+    let temperatures = [620, 560, 0, 600, 900, 3400, 8191, 3300].map(Temperature::new);
+    assert_eq!(
+        VirtualSensors::select(&temperatures),
+        VirtualSensors {
+            core: 1,
+            surface: 1,
+            ambient: 1,
+        }
+    );
+}
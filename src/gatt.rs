@@ -0,0 +1,380 @@
+//! The GATT services and characteristics Combustion devices expose, and decoding what's read
+//! from or notified on them.
+//!
+//! UUIDs are held as `u128`s, most significant byte first as they're normally written, the same
+//! as [`crate::advertising::AdvertisingData::service_uuids_128`].
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use deku::prelude::*;
+
+use crate::uart::node::{self, segmentation::Reassembler};
+use crate::uart::probe;
+use crate::{ProbeStatus, ProductType};
+
+#[cfg(test)]
+use crate::temperature::Temperature;
+#[cfg(test)]
+use crate::uart::node::segmentation::{segment, DEFAULT_ATT_MTU};
+#[cfg(test)]
+use crate::{BatteryStatus, Color, Mode, ProbeReadings};
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// The Bluetooth base UUID, which 16 bit UUIDs are short for.
+const BASE_UUID: u128 = 0x00000000_0000_1000_8000_00805f9b34fb;
+
+/// Expand a 16 bit UUID assigned by the Bluetooth SIG.
+pub const fn uuid_from_16(uuid: u16) -> u128 {
+    BASE_UUID | (uuid as u128) << 96
+}
+
+pub const PROBE_STATUS_SERVICE_UUID: u128 = 0x00000100_caab_3792_3d44_97ae51c1407a;
+pub const PROBE_STATUS_CHARACTERISTIC_UUID: u128 = 0x00000101_caab_3792_3d44_97ae51c1407a;
+/// The Nordic UART Service.
+pub const UART_SERVICE_UUID: u128 = 0x6e400001_b5a3_f393_e0a9_e50e24dcca9e;
+/// Written to send to the device.
+pub const UART_RX_CHARACTERISTIC_UUID: u128 = 0x6e400002_b5a3_f393_e0a9_e50e24dcca9e;
+/// Notified with what the device sends.
+pub const UART_TX_CHARACTERISTIC_UUID: u128 = 0x6e400003_b5a3_f393_e0a9_e50e24dcca9e;
+pub const DEVICE_INFORMATION_SERVICE_UUID: u128 = uuid_from_16(0x180a);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Service {
+    ProbeStatus,
+    Uart,
+    DeviceInformation,
+}
+
+impl Service {
+    pub const ALL: [Service; 3] = [
+        Service::ProbeStatus,
+        Service::Uart,
+        Service::DeviceInformation,
+    ];
+
+    pub fn uuid(&self) -> u128 {
+        match self {
+            Service::ProbeStatus => PROBE_STATUS_SERVICE_UUID,
+            Service::Uart => UART_SERVICE_UUID,
+            Service::DeviceInformation => DEVICE_INFORMATION_SERVICE_UUID,
+        }
+    }
+
+    pub fn from_uuid(uuid: u128) -> Option<Self> {
+        Self::ALL.into_iter().find(|service| service.uuid() == uuid)
+    }
+
+    pub fn characteristics(&self) -> impl Iterator<Item = Characteristic> + '_ {
+        Characteristic::ALL
+            .into_iter()
+            .filter(move |characteristic| characteristic.service() == *self)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Characteristic {
+    ProbeStatus,
+    UartRx,
+    UartTx,
+    ManufacturerName,
+    ModelNumber,
+    SerialNumber,
+    HardwareRevision,
+    FirmwareRevision,
+    SoftwareRevision,
+}
+
+impl Characteristic {
+    pub const ALL: [Characteristic; 9] = [
+        Characteristic::ProbeStatus,
+        Characteristic::UartRx,
+        Characteristic::UartTx,
+        Characteristic::ManufacturerName,
+        Characteristic::ModelNumber,
+        Characteristic::SerialNumber,
+        Characteristic::HardwareRevision,
+        Characteristic::FirmwareRevision,
+        Characteristic::SoftwareRevision,
+    ];
+
+    pub fn uuid(&self) -> u128 {
+        match self {
+            Characteristic::ProbeStatus => PROBE_STATUS_CHARACTERISTIC_UUID,
+            Characteristic::UartRx => UART_RX_CHARACTERISTIC_UUID,
+            Characteristic::UartTx => UART_TX_CHARACTERISTIC_UUID,
+            Characteristic::ManufacturerName => uuid_from_16(0x2a29),
+            Characteristic::ModelNumber => uuid_from_16(0x2a24),
+            Characteristic::SerialNumber => uuid_from_16(0x2a25),
+            Characteristic::HardwareRevision => uuid_from_16(0x2a27),
+            Characteristic::FirmwareRevision => uuid_from_16(0x2a26),
+            Characteristic::SoftwareRevision => uuid_from_16(0x2a28),
+        }
+    }
+
+    pub fn from_uuid(uuid: u128) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|characteristic| characteristic.uuid() == uuid)
+    }
+
+    pub fn service(&self) -> Service {
+        match self {
+            Characteristic::ProbeStatus => Service::ProbeStatus,
+            Characteristic::UartRx | Characteristic::UartTx => Service::Uart,
+            _ => Service::DeviceInformation,
+        }
+    }
+}
+
+/// What's on the other end of the UART, which decides how its messages are framed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UartPeer {
+    Probe,
+    Node,
+}
+
+impl UartPeer {
+    pub fn for_product(product_type: ProductType) -> Self {
+        match product_type {
+            ProductType::PredictiveProbe => UartPeer::Probe,
+            _ => UartPeer::Node,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GattMessage {
+    ProbeStatus(ProbeStatus),
+    /// Every probe response completed by the value, in order. Responses longer than a
+    /// notification are split across values, so this can be empty.
+    ProbeResponses(Vec<probe::response::Response>),
+    /// Every node message completed by the value, in order. Node messages can be split across
+    /// values, so this can be empty.
+    NodeMessages(Vec<node::MessageType>),
+    DeviceInformation {
+        characteristic: Characteristic,
+        value: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum GattError {
+    UnknownCharacteristic(u128),
+    /// The characteristic is only ever written to.
+    WriteOnly(Characteristic),
+    Decode(DekuError),
+}
+
+impl From<DekuError> for GattError {
+    fn from(error: DekuError) -> Self {
+        GattError::Decode(error)
+    }
+}
+
+/// Turns values read or notified from a device's characteristics into messages. Keeps what's
+/// been received of any partial UART message until the rest arrives, so there should be one
+/// per connection.
+#[derive(Debug)]
+pub struct GattDispatcher {
+    uart_peer: UartPeer,
+    reassembler: Reassembler,
+}
+
+impl GattDispatcher {
+    pub fn new(uart_peer: UartPeer) -> Self {
+        Self {
+            uart_peer,
+            reassembler: match uart_peer {
+                UartPeer::Probe => Reassembler::with_frame_length(probe::response::frame_length),
+                UartPeer::Node => Reassembler::new(),
+            },
+        }
+    }
+
+    pub fn uart_peer(&self) -> UartPeer {
+        self.uart_peer
+    }
+
+    pub fn handle(&mut self, uuid: u128, value: &[u8]) -> Result<GattMessage, GattError> {
+        let characteristic =
+            Characteristic::from_uuid(uuid).ok_or(GattError::UnknownCharacteristic(uuid))?;
+
+        match characteristic {
+            Characteristic::ProbeStatus => {
+                let (_, status) = ProbeStatus::from_bytes((value, 0))?;
+                Ok(GattMessage::ProbeStatus(status))
+            }
+            Characteristic::UartRx => Err(GattError::WriteOnly(characteristic)),
+            Characteristic::UartTx => match self.uart_peer {
+                UartPeer::Probe => Ok(GattMessage::ProbeResponses(self.probe_responses(value))),
+                UartPeer::Node => Ok(GattMessage::NodeMessages(self.node_messages(value))),
+            },
+            _ => Ok(GattMessage::DeviceInformation {
+                characteristic,
+                value: device_information_string(value),
+            }),
+        }
+    }
+
+    /// Frames that pass their CRC but can't be parsed, such as message types we don't know, are
+    /// skipped.
+    fn node_messages(&mut self, value: &[u8]) -> Vec<node::MessageType> {
+        self.reassembler
+            .push(value)
+            .iter()
+            .filter_map(|frame| node::try_request_or_response_from(frame).ok())
+            .collect()
+    }
+
+    /// Like node frames, responses that pass their CRC but can't be parsed are skipped.
+    fn probe_responses(&mut self, value: &[u8]) -> Vec<probe::response::Response> {
+        self.reassembler
+            .push(value)
+            .iter()
+            .filter_map(|frame| {
+                probe::response::Response::from_bytes((frame, 0))
+                    .ok()
+                    .map(|(_, response)| response)
+            })
+            .collect()
+    }
+}
+
+/// Device Information strings are UTF-8, sometimes with trailing NULs.
+fn device_information_string(value: &[u8]) -> String {
+    let end = value
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |last| last + 1);
+    String::from_utf8_lossy(&value[..end]).into_owned()
+}
+
+#[test]
+fn test_uuids() {
+    assert_eq!(
+        DEVICE_INFORMATION_SERVICE_UUID,
+        0x0000180a_0000_1000_8000_00805f9b34fb
+    );
+    assert_eq!(
+        Characteristic::from_uuid(0x00002a26_0000_1000_8000_00805f9b34fb),
+        Some(Characteristic::FirmwareRevision)
+    );
+    assert_eq!(
+        Service::Uart.characteristics().collect::<Vec<_>>(),
+        vec![Characteristic::UartRx, Characteristic::UartTx]
+    );
+    assert_eq!(
+        Service::from_uuid(PROBE_STATUS_SERVICE_UUID),
+        Some(Service::ProbeStatus)
+    );
+}
+
+#[test]
+fn test_dispatch() {
+    let mut dispatcher = GattDispatcher::new(UartPeer::Probe);
+
+    let status = [
+        0x00, 0x00, 0x00, 0x00, 0x63, 0x00, 0x00, 0x00, 0x4a, 0x63, 0x69, 0x2c, 0x8d, 0xa5, 0x31,
+        0x35, 0xaa, 0x46, 0xd5, 0xc0, 0x1a, 0x00, 0xc0, 0x00, 0x00, 0x00, 0xf0, 0xff, 0xbf, 0x34,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+    let Ok(GattMessage::ProbeStatus(status)) =
+        dispatcher.handle(PROBE_STATUS_CHARACTERISTIC_UUID, &status)
+    else {
+        panic!("Expected a probe status");
+    };
+    assert_eq!(status.get_temperatures()[0], Temperature::new(842));
+    assert_eq!(status.mode, Mode::Normal);
+    assert_eq!(status.color, Color::Yellow);
+    assert_eq!(status.battery_status, BatteryStatus::Ok);
+
+    let response = [202, 254, 188, 168, 3, 1, 6, 188, 254, 245, 34, 136, 19];
+    let Ok(GattMessage::ProbeResponses(responses)) =
+        dispatcher.handle(UART_TX_CHARACTERISTIC_UUID, &[response, response].concat())
+    else {
+        panic!("Expected probe responses");
+    };
+    assert_eq!(responses.len(), 2);
+
+    assert_eq!(
+        dispatcher.handle(uuid_from_16(0x2a29), b"Combustion Inc.\0"),
+        Ok(GattMessage::DeviceInformation {
+            characteristic: Characteristic::ManufacturerName,
+            value: String::from("Combustion Inc."),
+        })
+    );
+    assert_eq!(
+        dispatcher.handle(UART_RX_CHARACTERISTIC_UUID, &[]),
+        Err(GattError::WriteOnly(Characteristic::UartRx))
+    );
+    assert_eq!(
+        dispatcher.handle(uuid_from_16(0x2a00), &[]),
+        Err(GattError::UnknownCharacteristic(uuid_from_16(0x2a00)))
+    );
+}
+
+#[test]
+fn test_dispatch_node_frames_across_notifications() {
+    let mut dispatcher = GattDispatcher::new(UartPeer::for_product(ProductType::MeatNetRepeater));
+    let frame = node::request::Request::new_with_id(
+        node::request::RequestMessage::ReadSessionInformation(
+            node::request::ReadSessionInformation {
+                serial_number: crate::SerialNumber { number: 0x10001ded },
+            },
+        ),
+        0x12345678,
+    )
+    .to_bytes()
+    .unwrap();
+
+    assert_eq!(
+        dispatcher.handle(UART_TX_CHARACTERISTIC_UUID, &frame[..6]),
+        Ok(GattMessage::NodeMessages(vec![]))
+    );
+    let Ok(GattMessage::NodeMessages(messages)) =
+        dispatcher.handle(UART_TX_CHARACTERISTIC_UUID, &frame[6..])
+    else {
+        panic!("Expected node messages");
+    };
+    assert!(matches!(
+        messages.as_slice(),
+        [node::MessageType::Request(request)] if request.request_header.request_id == 0x12345678
+    ));
+}
+
+#[test]
+fn test_dispatch_probe_responses_across_notifications() {
+    let mut dispatcher = GattDispatcher::new(UartPeer::for_product(ProductType::PredictiveProbe));
+    let response = vec![
+        0xca, 0xfe, 0x26, 0xb9, 0x04, 0x01, 0x18, 0x09, 0x00, 0x00, 0x00, 0x6e, 0xe5, 0xad, 0x98,
+        0x95, 0xa6, 0x82, 0x50, 0x88, 0x89, 0x24, 0x69, 0x23, 0x70, 0x00, 0x00, 0xfe, 0xff, 0xd7,
+        0x0a,
+    ];
+    let fragments: Vec<_> = segment(&response, DEFAULT_ATT_MTU).collect();
+    assert_eq!(fragments.len(), 2);
+
+    assert_eq!(
+        dispatcher.handle(UART_TX_CHARACTERISTIC_UUID, fragments[0]),
+        Ok(GattMessage::ProbeResponses(vec![]))
+    );
+    let Ok(GattMessage::ProbeResponses(responses)) =
+        dispatcher.handle(UART_TX_CHARACTERISTIC_UUID, fragments[1])
+    else {
+        panic!("Expected probe responses");
+    };
+    assert!(matches!(
+        responses.as_slice(),
+        [probe::response::Response {
+            message: probe::response::ResponseMessage::ReadLogs(probe::response::ReadLogs {
+                sequence_number: 9,
+                ..
+            }),
+            ..
+        }]
+    ));
+}
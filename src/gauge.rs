@@ -0,0 +1,155 @@
+extern crate alloc;
+
+#[cfg(test)]
+use alloc::vec;
+use alloc::{format, vec::Vec};
+use deku::{
+    ctx::Endian,
+    no_std_io::{Read, Seek, Write},
+    prelude::*,
+};
+
+use crate::{temperature::Temperature, NetworkInformation, NodeSerialNumber, ProductType};
+
+#[cfg(test)]
+use crate::Hops;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Gauges report a single 13 bit temperature in the low bits of a little endian u16.
+pub(crate) fn read_gauge_temperature<R: Read + Seek>(
+    reader: &mut Reader<R>,
+) -> Result<Temperature, DekuError> {
+    let raw = u16::from_reader_with_ctx(reader, Endian::Little)?;
+    Ok(Temperature::new(raw & 0x1fff))
+}
+
+pub(crate) fn write_gauge_temperature<W: Write + Seek>(
+    writer: &mut Writer<W>,
+    temperature: &Temperature,
+) -> Result<(), DekuError> {
+    (temperature.get_raw_value() & 0x1fff).to_writer(writer, Endian::Little)
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy)]
+pub struct GaugeStatusFlags {
+    #[deku(bits = "1", pad_bits_before = "5")]
+    pub low_battery: bool,
+    #[deku(bits = "1")]
+    pub sensor_overheating: bool,
+    #[deku(bits = "1")]
+    pub sensor_present: bool,
+}
+
+/// State of one of the gauge's alarms. On the wire this is a little endian u16 with the flags in
+/// bits 0-2 and the alarm temperature in bits 3-15.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AlarmStatus {
+    pub set: bool,
+    pub tripped: bool,
+    pub alarming: bool,
+    pub temperature: Temperature,
+}
+
+impl AlarmStatus {
+    pub fn from_raw(raw: u16) -> Self {
+        Self {
+            set: raw & 0b001 != 0,
+            tripped: raw & 0b010 != 0,
+            alarming: raw & 0b100 != 0,
+            temperature: Temperature::new(raw >> 3),
+        }
+    }
+
+    pub fn to_raw(&self) -> u16 {
+        (self.set as u16)
+            | (self.tripped as u16) << 1
+            | (self.alarming as u16) << 2
+            | (self.temperature.get_raw_value() & 0x1fff) << 3
+    }
+}
+
+impl<'a> DekuReader<'a> for AlarmStatus {
+    fn from_reader_with_ctx<R: Read + Seek>(
+        reader: &mut Reader<R>,
+        _: (),
+    ) -> Result<Self, DekuError> {
+        Ok(Self::from_raw(u16::from_reader_with_ctx(
+            reader,
+            Endian::Little,
+        )?))
+    }
+}
+
+impl DekuWriter for AlarmStatus {
+    fn to_writer<W: Write + Seek>(&self, writer: &mut Writer<W>, _: ()) -> Result<(), DekuError> {
+        self.to_raw().to_writer(writer, Endian::Little)
+    }
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy)]
+pub struct HighLowAlarmStatus {
+    pub high: AlarmStatus,
+    pub low: AlarmStatus,
+}
+
+/// Advertising data sent by a Giant Grill Gauge, or by a MeatNet node repeating one.
+#[derive(Debug, PartialEq, DekuRead, Clone)]
+#[deku(magic = b"\xc7\x09")]
+pub struct GaugeManufacturerSpecificData {
+    #[deku(assert = "*product_type == ProductType::Gauge")]
+    pub product_type: ProductType,
+    pub serial_number: NodeSerialNumber,
+    #[deku(reader = "read_gauge_temperature(deku::reader)")]
+    pub temperature: Temperature,
+    pub status_flags: GaugeStatusFlags,
+    pub network_information: NetworkInformation,
+    pub high_low_alarm_status: HighLowAlarmStatus,
+}
+
+#[test]
+fn test_alarm_status_raw_round_trip() {
+    let status = AlarmStatus::from_raw(0x1b55);
+
+    assert_eq!(
+        status,
+        AlarmStatus {
+            set: true,
+            tripped: false,
+            alarming: true,
+            temperature: Temperature::new(874),
+        }
+    );
+    assert_eq!(status.to_raw(), 0x1b55);
+}
+
+#[test]
+fn test_gauge_manufacturer_specific_data() {
+    let data = vec![
+        0xc7, 0x09, 0x03, 0x47, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x31, 0x32, 0x41, 0x6a, 0x23,
+        0x01, 0x00, 0x55, 0x1b, 0x00, 0x00,
+    ];
+
+    assert_eq!(
+        GaugeManufacturerSpecificData::from_bytes((data.as_slice(), 0))
+            .unwrap()
+            .1,
+        GaugeManufacturerSpecificData {
+            product_type: ProductType::Gauge,
+            serial_number: "G00000012A".parse().unwrap(),
+            temperature: Temperature::new(874),
+            status_flags: GaugeStatusFlags {
+                low_battery: false,
+                sensor_overheating: false,
+                sensor_present: true,
+            },
+            network_information: NetworkInformation {
+                hop_count: Hops::One
+            },
+            high_low_alarm_status: HighLowAlarmStatus {
+                high: AlarmStatus::from_raw(0x1b55),
+                low: AlarmStatus::from_raw(0),
+            },
+        }
+    );
+}
@@ -1,21 +1,36 @@
 #![no_std]
 
+pub mod advertising;
+pub mod aggregator;
+pub mod analysis;
+pub mod clock;
+pub mod gatt;
+pub mod gauge;
+pub mod presence;
+pub mod repeater;
+pub mod rssi;
+pub mod serial_number;
+pub mod sim;
 pub mod temperature;
+pub mod topology;
 pub mod uart;
 
 extern crate alloc;
+#[cfg(any(feature = "std", feature = "async"))]
+extern crate std;
 
-use alloc::{borrow::Cow, format, string::ToString, vec::Vec};
-use bitvec::prelude::*;
+use alloc::{format, vec::Vec};
+use core::fmt;
 use deku::{
     ctx::BitSize,
-    no_std_io::{Read, Seek},
+    no_std_io::{Read, Seek, Write},
     prelude::*,
     DekuReader,
 };
 use serde::{Deserialize, Serialize};
 
-use temperature::Temperature;
+pub use serial_number::{DeviceSerial, NodeSerialNumber};
+use temperature::{ReadingStatus, Temperature};
 
 #[cfg(test)]
 use alloc::vec;
@@ -24,12 +39,16 @@ use deku::no_std_io::Cursor;
 #[cfg(test)]
 use pretty_assertions::assert_eq;
 
+/// Approximate distance of each sensor from the tip of the probe in millimetres, from T1 at the
+/// tip to T8 in the handle.
+pub const SENSOR_POSITIONS_MM: [f32; 8] = [3.0, 15.5, 28.0, 40.5, 53.0, 65.5, 78.0, 115.0];
+
 pub trait EncapsulatableMessage {
     type Encapsulation;
     fn encapsulate(self) -> Self::Encapsulation;
 }
 
-#[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, DekuWrite, DekuRead)]
 #[deku(id_type = "u8")]
 pub enum Hops {
     One = 0,
@@ -38,6 +57,12 @@ pub enum Hops {
     Four,
 }
 
+impl Hops {
+    pub fn count(&self) -> u8 {
+        *self as u8 + 1
+    }
+}
+
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
 #[deku(bits = "2", id_type = "u8")]
 pub enum PredictionMode {
@@ -77,17 +102,26 @@ pub enum PredictionState {
     Unknown,
 }
 
-#[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+#[derive(Debug, PartialEq, Clone, Copy, DekuWrite, DekuRead)]
 pub struct NetworkInformation {
     pub hop_count: Hops,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+/// Manufacturer data advertised by probes, and by nodes repeating them.
+///
+/// Gauges advertise a different layout, [`gauge::GaugeManufacturerSpecificData`], and fail to
+/// parse as this. To handle adverts from either, use [`advertising::CombustionData::parse`],
+/// which picks the layout from the product type.
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone)]
 #[deku(magic = b"\xc7\x09")]
 pub struct ManufacturerSpecificData {
+    #[deku(assert = "*product_type != ProductType::Gauge")]
     pub product_type: ProductType,
     pub probe_serial_number: SerialNumber,
-    #[deku(reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))")]
+    #[deku(
+        reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))",
+        writer = "write_raw_temperature_data(deku::writer, &self.temperatures)"
+    )]
     pub temperatures: [Temperature; 8],
     #[deku(bits = "3")]
     pub probe_id: u8,
@@ -101,12 +135,9 @@ pub struct ManufacturerSpecificData {
     virtual_core_sensor: u8,
     pub battery_status: BatteryStatus,
     #[deku(
-        cond = "product_type == &ProductType::MeatNetRepeater",
+        cond = "product_type.is_meatnet_node()",
         default = "None",
-        pad_bytes_after = "match product_type {
-            ProductType::MeatNetRepeater => 1,
-            _ => 2,
-        }"
+        pad_bytes_after = "if product_type.is_meatnet_node() { 1 } else { 2 }"
     )]
     pub network_information: Option<NetworkInformation>,
 }
@@ -125,33 +156,122 @@ impl ManufacturerSpecificData {
     }
 }
 
+/// The readings along a probe, and which sensors it has picked as its virtual core, surface and
+/// ambient sensors.
+pub trait ProbeReadings {
+    fn get_temperatures(&self) -> &[Temperature; 8];
+
+    /// `None` where the mode isn't sent, as in log entries.
+    fn get_mode(&self) -> Option<Mode>;
+
+    /// Which of T1 to T6 is the core.
+    fn get_virtual_core_sensor(&self) -> u8;
+
+    /// Which of T4 to T7 is the surface, counting from T4.
+    fn get_virtual_surface_sensor(&self) -> u8;
+
+    /// Which of T5 to T8 is the ambient, counting from T5.
+    fn get_virtual_ambient_sensor(&self) -> u8;
+
+    /// Whether each sensor's reading can be used. Every sensor is errored if the probe is.
+    fn get_reading_statuses(&self) -> [ReadingStatus; 8] {
+        let errored = self.get_mode() == Some(Mode::Errored);
+        self.get_temperatures().map(|temperature| match errored {
+            true => ReadingStatus::Errored,
+            false => temperature.get_reading_status(),
+        })
+    }
+
+    /// Each reading, or `None` for sensors that aren't giving real readings.
+    fn get_valid_temperatures(&self) -> [Option<Temperature>; 8] {
+        let errored = self.get_mode() == Some(Mode::Errored);
+        self.get_temperatures().map(|temperature| match errored {
+            true => None,
+            false => temperature.valid(),
+        })
+    }
+
+    /// In Instant Read mode there are no virtual sensors, so this is `None`.
+    fn get_valid_core_temperature(&self) -> Option<Temperature> {
+        valid_virtual_temperature(self, self.get_virtual_core_sensor())
+    }
+
+    /// In Instant Read mode there are no virtual sensors, so this is `None`.
+    fn get_valid_surface_temperature(&self) -> Option<Temperature> {
+        valid_virtual_temperature(self, self.get_virtual_surface_sensor() + 3)
+    }
+
+    /// In Instant Read mode there are no virtual sensors, so this is `None`.
+    fn get_valid_ambient_temperature(&self) -> Option<Temperature> {
+        valid_virtual_temperature(self, self.get_virtual_ambient_sensor() + 4)
+    }
+
+    /// The instant read value, only sent in Instant Read mode, as T1.
+    fn get_instant_read_temperature(&self) -> Option<Temperature> {
+        match self.get_mode() {
+            Some(Mode::InstantRead) => self.get_temperatures()[0].valid(),
+            _ => None,
+        }
+    }
+}
+
+fn valid_virtual_temperature<R: ProbeReadings + ?Sized>(
+    readings: &R,
+    index: u8,
+) -> Option<Temperature> {
+    match readings.get_mode() {
+        Some(Mode::InstantRead) => None,
+        _ => *readings.get_valid_temperatures().get(index as usize)?,
+    }
+}
+
+impl ProbeReadings for ManufacturerSpecificData {
+    fn get_temperatures(&self) -> &[Temperature; 8] {
+        &self.temperatures
+    }
+
+    fn get_mode(&self) -> Option<Mode> {
+        Some(self.mode)
+    }
+
+    fn get_virtual_core_sensor(&self) -> u8 {
+        self.virtual_core_sensor
+    }
+
+    fn get_virtual_surface_sensor(&self) -> u8 {
+        self.virtual_surface_sensor
+    }
+
+    fn get_virtual_ambient_sensor(&self) -> u8 {
+        self.virtual_ambient_sensor
+    }
+}
+
 fn parse_raw_temperature_data<R: Read + Seek>(
     reader: &mut Reader<R>,
     bit_size: BitSize,
 ) -> Result<[Temperature; 8], DekuError> {
     let bytes = <[u8; 13]>::from_reader_with_ctx(reader, bit_size)?;
+    Ok(temperature::unpack_temperatures(bytes))
+}
 
-    match bytes
-        .into_bitarray::<Lsb0>()
-        .chunks(13)
-        .map(|chunk| (Temperature::new(chunk.load_le())))
-        .collect::<Vec<Temperature>>()
-        .try_into()
-    {
-        Ok(raw_temperatures) => Ok(raw_temperatures),
-        Err(e) => Err(DekuError::Parse(Cow::from(
-            format!("Unable to parse raw temperatures: {:?}", e).to_string(),
-        ))),
-    }
+fn write_raw_temperature_data<W: Write + Seek>(
+    writer: &mut Writer<W>,
+    temperatures: &[Temperature; 8],
+) -> Result<(), DekuError> {
+    temperature::pack_temperatures(temperatures).to_writer(writer, ())
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone)]
 pub struct ProbeStatus {
     #[deku(endian = "little")]
     pub log_start: u32,
     #[deku(endian = "little")]
     pub log_end: u32,
-    #[deku(reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))")]
+    #[deku(
+        reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))",
+        writer = "write_raw_temperature_data(deku::writer, &self.temperatures)"
+    )]
     temperatures: [Temperature; 8],
     #[deku(bits = "3")]
     pub probe_id: u8,
@@ -163,8 +283,11 @@ pub struct ProbeStatus {
     virtual_surface_sensor: u8,
     #[deku(bits = "3")]
     virtual_core_sensor: u8,
-    #[deku(pad_bytes_after = "25")]
     pub battery_status: BatteryStatus,
+    // These aren't decoded yet, but are kept so the status can be re-encoded unchanged.
+    pub prediction_status: [u8; 7],
+    pub food_safe_data: [u8; 10],
+    pub food_safe_status: [u8; 8],
 }
 
 impl ProbeStatus {
@@ -181,7 +304,49 @@ impl ProbeStatus {
     }
 }
 
-#[derive(Debug, PartialEq, DekuRead, Clone)]
+impl ProbeReadings for ProbeStatus {
+    fn get_temperatures(&self) -> &[Temperature; 8] {
+        &self.temperatures
+    }
+
+    fn get_mode(&self) -> Option<Mode> {
+        Some(self.mode)
+    }
+
+    fn get_virtual_core_sensor(&self) -> u8 {
+        self.virtual_core_sensor
+    }
+
+    fn get_virtual_surface_sensor(&self) -> u8 {
+        self.virtual_surface_sensor
+    }
+
+    fn get_virtual_ambient_sensor(&self) -> u8 {
+        self.virtual_ambient_sensor
+    }
+}
+
+/// A status in normal mode with readings `temperatures`, for tests to adjust as they need.
+#[cfg(test)]
+pub(crate) fn test_status(temperatures: [Temperature; 8]) -> ProbeStatus {
+    ProbeStatus {
+        log_start: 0,
+        log_end: 99,
+        temperatures,
+        probe_id: 0,
+        color: Color::Yellow,
+        mode: Mode::Normal,
+        virtual_ambient_sensor: 3,
+        virtual_surface_sensor: 0,
+        virtual_core_sensor: 0,
+        battery_status: BatteryStatus::Ok,
+        prediction_status: [0; 7],
+        food_safe_data: [0; 10],
+        food_safe_status: [0; 8],
+    }
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy)]
 #[deku(id_type = "u8", bits = "2")]
 pub enum Mode {
     Normal = 0,
@@ -190,7 +355,7 @@ pub enum Mode {
     Errored,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy)]
 #[deku(id_type = "u8", bits = "3")]
 pub enum Color {
     Yellow = 0,
@@ -203,28 +368,61 @@ pub enum Color {
     Reserved7,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy)]
 #[deku(id_type = "u8", bits = "1")]
 pub enum BatteryStatus {
     Ok = 0,
     LowBattery,
 }
 
-#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, DekuRead, DekuWrite, Clone, Copy, Serialize, Deserialize)]
 #[deku(id_type = "u8")]
 pub enum ProductType {
     Unknown = 0,
     PredictiveProbe,
     MeatNetRepeater,
+    Gauge,
+    Display,
+    Booster,
+}
+
+impl ProductType {
+    /// Products that take part in MeatNet as repeaters rather than as thermometers.
+    pub fn is_meatnet_node(&self) -> bool {
+        matches!(
+            self,
+            ProductType::MeatNetRepeater | ProductType::Display | ProductType::Booster
+        )
+    }
 }
 
-#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy, Serialize, Deserialize)]
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    DekuRead,
+    DekuWrite,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+)]
 #[deku(endian = "little")]
 pub struct SerialNumber {
     pub number: u32,
 }
 
-#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+/// Probe serial numbers are printed as 8 hex digits, as on the probe's packaging.
+impl fmt::Display for SerialNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08X}", self.number)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, DekuRead, DekuWrite, Clone, Copy)]
 pub struct MacAddress {
     pub address: [u8; 6],
 }
@@ -242,6 +440,14 @@ fn test_parse_raw_temperature_data() {
         Ok(raw_temperatures) => raw_temperatures,
         Err(e) => panic!("Error: {}", e),
     };
+
+    let mut written = [0u8; 13];
+    let mut out = Cursor::new(written.as_mut_slice());
+    let mut writer = Writer::new(&mut out);
+    write_raw_temperature_data(&mut writer, &raw_temperatures).unwrap();
+    writer.finalize().unwrap();
+    assert_eq!(written, data);
+
     assert_eq!(
         raw_temperatures,
         [
@@ -289,8 +495,54 @@ fn test_probe_status() {
             virtual_surface_sensor: 0,
             virtual_core_sensor: 0,
             battery_status: BatteryStatus::Ok,
+            prediction_status: [0x00, 0x00, 0x00, 0xf0, 0xff, 0xbf, 0x34],
+            food_safe_data: [0; 10],
+            food_safe_status: [0; 8],
         }
     );
+    assert_eq!(probe_status.to_bytes().unwrap(), data[..48]);
+}
+
+#[test]
+fn test_probe_status_reading_validity() {
+    let mut probe_status =
+        test_status([842, 0, 843, 843, 851, 853, 853, 8191].map(Temperature::new));
+    assert_eq!(
+        probe_status.get_reading_statuses()[1],
+        ReadingStatus::Underrange
+    );
+    assert_eq!(
+        probe_status.get_valid_core_temperature(),
+        Some(Temperature::new(842))
+    );
+    assert_eq!(probe_status.get_valid_ambient_temperature(), None);
+
+    probe_status.mode = Mode::Errored;
+    assert_eq!(
+        probe_status.get_reading_statuses(),
+        [ReadingStatus::Errored; 8]
+    );
+    assert_eq!(probe_status.get_valid_temperatures(), [None; 8]);
+}
+
+#[test]
+fn test_probe_status_instant_read() {
+    let mut probe_status = ProbeStatus {
+        mode: Mode::InstantRead,
+        ..test_status([1250, 0, 0, 0, 0, 0, 0, 0].map(Temperature::new))
+    };
+    assert_eq!(
+        probe_status.get_instant_read_temperature(),
+        Some(Temperature::new(1250))
+    );
+    assert_eq!(probe_status.get_valid_core_temperature(), None);
+
+    probe_status.mode = Mode::Normal;
+    assert_eq!(probe_status.get_instant_read_temperature(), None);
+    assert_eq!(
+        probe_status.get_valid_core_temperature(),
+        Some(Temperature::new(1250))
+    );
 }
 
 #[test]
@@ -329,6 +581,14 @@ fn test_manufacturer_specific_data() {
             .unwrap()
             .1,
     );
+    assert_eq!(
+        ManufacturerSpecificData::from_bytes((node_data.as_slice(), 0))
+            .unwrap()
+            .1
+            .to_bytes()
+            .unwrap(),
+        node_data
+    );
 
     let probe_data = vec![
         0xc7, 0x09, 0x01, 0xed, 0x1d, 0x00, 0x10, 0xc7, 0x84, 0x97, 0xdc, 0x92, 0x51, 0x12, 0x47,
@@ -362,4 +622,12 @@ fn test_manufacturer_specific_data() {
             .unwrap()
             .1,
     );
+    assert_eq!(
+        ManufacturerSpecificData::from_bytes((probe_data.as_slice(), 0))
+            .unwrap()
+            .1
+            .to_bytes()
+            .unwrap(),
+        probe_data
+    );
 }
@@ -0,0 +1,231 @@
+//! Tracks whether each probe is still being heard from, so a probe that has gone out of range
+//! or back in its charger isn't shown with its last reading forever.
+
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::time::Duration;
+
+use crate::clock::Clock;
+use crate::uart::node::request::ProbeStatusMessage;
+use crate::{ManufacturerSpecificData, ProbeStatus, ProductType, SerialNumber};
+
+#[cfg(test)]
+use crate::clock::ManualClock;
+#[cfg(test)]
+use crate::{temperature::Temperature, test_status};
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Presence {
+    /// Heard from within `stale_after`.
+    Live,
+    /// Not heard from for `stale_after`, its last reading may be out of date.
+    Stale,
+    /// Not heard from for `lost_after`.
+    Lost,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PresenceConfig {
+    pub stale_after: Duration,
+    pub lost_after: Duration,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            stale_after: Duration::from_secs(10),
+            lost_after: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PresenceEvent {
+    pub serial_number: SerialNumber,
+    /// `None` the first time a probe is seen.
+    pub from: Option<Presence>,
+    pub to: Presence,
+}
+
+#[derive(Debug)]
+struct Entry {
+    last_seen: Duration,
+    presence: Presence,
+}
+
+#[derive(Debug)]
+pub struct PresenceTracker<C: Clock> {
+    clock: C,
+    config: PresenceConfig,
+    probes: BTreeMap<SerialNumber, Entry>,
+    events: Vec<PresenceEvent>,
+}
+
+impl<C: Clock> PresenceTracker<C> {
+    pub fn new(clock: C, config: PresenceConfig) -> Self {
+        Self {
+            clock,
+            config,
+            probes: BTreeMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Advertisements from the probe itself or from a node repeating it both count.
+    pub fn observe_advertisement(&mut self, advertisement: &ManufacturerSpecificData) {
+        if advertisement.product_type == ProductType::PredictiveProbe
+            || advertisement.network_information.is_some()
+        {
+            self.observe(advertisement.probe_serial_number);
+        }
+    }
+
+    pub fn observe_status(&mut self, serial_number: SerialNumber, _status: &ProbeStatus) {
+        self.observe(serial_number);
+    }
+
+    pub fn observe_status_message(&mut self, message: &ProbeStatusMessage) {
+        self.observe(message.probe_serial_number);
+    }
+
+    pub fn observe(&mut self, serial_number: SerialNumber) {
+        let now = self.clock.now();
+
+        let from = match self.probes.get_mut(&serial_number) {
+            Some(entry) => {
+                entry.last_seen = now;
+                if entry.presence == Presence::Live {
+                    return;
+                }
+                let from = entry.presence;
+                entry.presence = Presence::Live;
+                Some(from)
+            }
+            None => {
+                self.probes.insert(
+                    serial_number,
+                    Entry {
+                        last_seen: now,
+                        presence: Presence::Live,
+                    },
+                );
+                None
+            }
+        };
+
+        self.events.push(PresenceEvent {
+            serial_number,
+            from,
+            to: Presence::Live,
+        });
+    }
+
+    /// Re-classify every probe against the clock and return the transitions since the last
+    /// call, oldest first.
+    pub fn poll(&mut self) -> Vec<PresenceEvent> {
+        let now = self.clock.now();
+
+        for (serial_number, entry) in self.probes.iter_mut() {
+            let age = now.saturating_sub(entry.last_seen);
+            let presence = if age >= self.config.lost_after {
+                Presence::Lost
+            } else if age >= self.config.stale_after {
+                Presence::Stale
+            } else {
+                Presence::Live
+            };
+
+            if presence != entry.presence {
+                self.events.push(PresenceEvent {
+                    serial_number: *serial_number,
+                    from: Some(entry.presence),
+                    to: presence,
+                });
+                entry.presence = presence;
+            }
+        }
+
+        core::mem::take(&mut self.events)
+    }
+
+    /// The presence as of the last [`PresenceTracker::poll`] or observation.
+    pub fn presence(&self, serial_number: &SerialNumber) -> Option<Presence> {
+        self.probes.get(serial_number).map(|entry| entry.presence)
+    }
+
+    pub fn last_seen(&self, serial_number: &SerialNumber) -> Option<Duration> {
+        self.probes.get(serial_number).map(|entry| entry.last_seen)
+    }
+
+    /// Stop tracking a probe, e.g. once the UI has dismissed a lost one.
+    pub fn forget(&mut self, serial_number: &SerialNumber) {
+        self.probes.remove(serial_number);
+    }
+}
+
+#[test]
+fn test_presence_transitions() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let status = test_status([Temperature::new(842); 8]);
+    let clock = ManualClock::new(Duration::ZERO);
+    let mut tracker = PresenceTracker::new(
+        &clock,
+        PresenceConfig {
+            stale_after: Duration::from_secs(5),
+            lost_after: Duration::from_secs(30),
+        },
+    );
+
+    tracker.observe_status(serial_number, &status);
+    clock.advance(Duration::from_secs(1));
+    tracker.observe_status(serial_number, &status);
+    assert_eq!(
+        tracker.poll(),
+        vec![PresenceEvent {
+            serial_number,
+            from: None,
+            to: Presence::Live,
+        }]
+    );
+
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(
+        tracker.poll(),
+        vec![PresenceEvent {
+            serial_number,
+            from: Some(Presence::Live),
+            to: Presence::Stale,
+        }]
+    );
+    assert_eq!(tracker.presence(&serial_number), Some(Presence::Stale));
+
+    clock.advance(Duration::from_secs(25));
+    assert_eq!(
+        tracker.poll(),
+        vec![PresenceEvent {
+            serial_number,
+            from: Some(Presence::Stale),
+            to: Presence::Lost,
+        }]
+    );
+    assert_eq!(tracker.poll(), vec![]);
+
+    tracker.observe(serial_number);
+    assert_eq!(
+        tracker.poll(),
+        vec![PresenceEvent {
+            serial_number,
+            from: Some(Presence::Lost),
+            to: Presence::Live,
+        }]
+    );
+    assert_eq!(
+        tracker.last_seen(&serial_number),
+        Some(Duration::from_secs(31))
+    );
+}
@@ -0,0 +1,354 @@
+//! MeatNet repeater logic with no I/O of its own.
+//!
+//! Feed it what the radio receives with the `handle_*` methods, call
+//! [`Repeater::handle_timeout`] once [`Repeater::poll_timeout`] has passed, and send whatever
+//! [`Repeater::poll_transmit`] returns to the other nodes.
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::uart::node::request::{
+    Attributes, ConnectionDetailRecord, Direction, HeartbeatMessage, ProbeStatusMessage, Request,
+    RequestMessage, SyncThermometerList,
+};
+use crate::{
+    DeviceSerial, Hops, MacAddress, NetworkInformation, NodeSerialNumber, ProbeStatus, ProductType,
+    SerialNumber,
+};
+
+#[cfg(test)]
+use crate::{temperature::Temperature, test_status};
+#[cfg(test)]
+use deku::DekuContainerWrite;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RepeaterConfig {
+    pub serial_number: NodeSerialNumber,
+    pub mac_address: MacAddress,
+    pub product_type: ProductType,
+    pub heartbeat_interval: Duration,
+    /// Peers that haven't been heard from for this long are left out of heartbeats.
+    pub peer_timeout: Duration,
+    /// How many recent request IDs are remembered to avoid forwarding the same message twice.
+    pub duplicate_history: usize,
+}
+
+impl RepeaterConfig {
+    pub fn new(serial_number: NodeSerialNumber, mac_address: MacAddress) -> Self {
+        Self {
+            serial_number,
+            mac_address,
+            product_type: ProductType::MeatNetRepeater,
+            heartbeat_interval: Duration::from_secs(5),
+            peer_timeout: Duration::from_secs(15),
+            duplicate_history: 64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Peer {
+    product_type: ProductType,
+    rssi: i8,
+    last_seen: Duration,
+}
+
+#[derive(Debug)]
+pub struct Repeater {
+    config: RepeaterConfig,
+    next_heartbeat: Duration,
+    peers: BTreeMap<DeviceSerial, Peer>,
+    sync_thermometer_lists: BTreeMap<MacAddress, SyncThermometerList>,
+    seen_request_ids: VecDeque<u32>,
+    transmit: VecDeque<Request>,
+}
+
+impl Repeater {
+    /// The first heartbeat is due straight away.
+    pub fn new(config: RepeaterConfig, now: Duration) -> Self {
+        Self {
+            config,
+            next_heartbeat: now,
+            peers: BTreeMap::new(),
+            sync_thermometer_lists: BTreeMap::new(),
+            seen_request_ids: VecDeque::new(),
+            transmit: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &RepeaterConfig {
+        &self.config
+    }
+
+    /// A status notification from a probe we're connected to directly.
+    pub fn handle_probe_status(
+        &mut self,
+        serial_number: SerialNumber,
+        status: ProbeStatus,
+        rssi: i8,
+        now: Duration,
+    ) {
+        self.handle_peer(
+            serial_number.into(),
+            ProductType::PredictiveProbe,
+            rssi,
+            now,
+        );
+
+        let request = Request::new(RequestMessage::ProbeStatusMessage(ProbeStatusMessage {
+            probe_serial_number: serial_number,
+            status,
+            network_information: NetworkInformation {
+                hop_count: Hops::One,
+            },
+        }));
+        self.remember(request.request_header.request_id);
+        self.transmit.push_back(request);
+    }
+
+    /// Note a device we're connected to, so it's listed in our heartbeats.
+    pub fn handle_peer(
+        &mut self,
+        serial_number: DeviceSerial,
+        product_type: ProductType,
+        rssi: i8,
+        now: Duration,
+    ) {
+        self.peers.insert(
+            serial_number,
+            Peer {
+                product_type,
+                rssi,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// A message from another node. Probe statuses and heartbeats are passed on one hop further,
+    /// unless we've already seen them or they've run out of hops.
+    pub fn handle_request(&mut self, request: Request) {
+        let request_id = request.request_header.request_id;
+        if self.seen_request_ids.contains(&request_id) {
+            return;
+        }
+        self.remember(request_id);
+
+        let message = match request.message {
+            RequestMessage::ProbeStatusMessage(mut message) => {
+                message.network_information.hop_count = match message.network_information.hop_count
+                {
+                    Hops::One => Hops::Two,
+                    Hops::Two => Hops::Three,
+                    Hops::Three => Hops::Four,
+                    Hops::Four => return,
+                };
+                RequestMessage::ProbeStatusMessage(message)
+            }
+            RequestMessage::HeartbeatMessage(mut message) => {
+                if message.node_serial_number == self.config.serial_number {
+                    return;
+                }
+                message.hop_count = match message.hop_count.checked_add(1) {
+                    Some(hop_count) => hop_count,
+                    None => return,
+                };
+                RequestMessage::HeartbeatMessage(message)
+            }
+            RequestMessage::SyncThermometerList(list) => {
+                self.sync_thermometer_lists
+                    .insert(*list.mac_address(), list);
+                return;
+            }
+            _ => return,
+        };
+
+        self.transmit
+            .push_back(Request::new_with_id(message, request_id));
+    }
+
+    /// When [`Repeater::handle_timeout`] next needs calling.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        Some(self.next_heartbeat)
+    }
+
+    pub fn handle_timeout(&mut self, now: Duration) {
+        let peer_timeout = self.config.peer_timeout;
+        self.peers
+            .retain(|_, peer| now.saturating_sub(peer.last_seen) <= peer_timeout);
+
+        if now < self.next_heartbeat {
+            return;
+        }
+        self.next_heartbeat = now + self.config.heartbeat_interval;
+
+        let heartbeat = Request::new(RequestMessage::HeartbeatMessage(self.heartbeat()));
+        self.remember(heartbeat.request_header.request_id);
+        self.transmit.push_back(heartbeat);
+
+        let probes = self.direct_probes();
+        if !probes.is_empty() {
+            let probes = &probes[..probes.len().min(SyncThermometerList::MAX_THERMOMETERS)];
+            if let Ok(list) = SyncThermometerList::new(self.config.mac_address, probes) {
+                let request = Request::new(RequestMessage::SyncThermometerList(list));
+                self.remember(request.request_header.request_id);
+                self.transmit.push_back(request);
+            }
+        }
+    }
+
+    /// The next message to send to the rest of MeatNet.
+    pub fn poll_transmit(&mut self) -> Option<Request> {
+        self.transmit.pop_front()
+    }
+
+    /// The latest list of connected probes from each node that has sent one.
+    pub fn sync_thermometer_lists(&self) -> impl Iterator<Item = &SyncThermometerList> {
+        self.sync_thermometer_lists.values()
+    }
+
+    fn direct_probes(&self) -> Vec<SerialNumber> {
+        self.peers
+            .keys()
+            .filter_map(|serial_number| match serial_number {
+                DeviceSerial::Probe(serial_number) => Some(*serial_number),
+                DeviceSerial::Node(_) => None,
+            })
+            .collect()
+    }
+
+    fn heartbeat(&self) -> HeartbeatMessage {
+        let mut peers: Vec<_> = self.peers.iter().collect();
+        // Heartbeats only have room for four connections, so list the strongest.
+        peers.sort_by_key(|(_, peer)| core::cmp::Reverse(peer.rssi));
+
+        let record = |index: usize| match peers.get(index) {
+            Some((serial_number, peer)) => ConnectionDetailRecord {
+                serial_number: **serial_number,
+                product_type: peer.product_type,
+                attributes: Attributes {
+                    connection_detail_record_is_populated: true,
+                },
+                rssi: peer.rssi as u8,
+            },
+            None => ConnectionDetailRecord {
+                serial_number: DeviceSerial::Node(NodeSerialNumber::new([0; 10])),
+                product_type: ProductType::Unknown,
+                attributes: Attributes {
+                    connection_detail_record_is_populated: false,
+                },
+                rssi: 0,
+            },
+        };
+
+        HeartbeatMessage {
+            node_serial_number: self.config.serial_number,
+            mac_address: self.config.mac_address,
+            product_type: self.config.product_type,
+            hop_count: 0,
+            is_inbound: Direction::Inbound,
+            connection_details: [record(0), record(1), record(2), record(3)],
+        }
+    }
+
+    fn remember(&mut self, request_id: u32) {
+        self.seen_request_ids.push_back(request_id);
+        while self.seen_request_ids.len() > self.config.duplicate_history {
+            self.seen_request_ids.pop_front();
+        }
+    }
+}
+
+#[test]
+fn test_forwards_probe_status_once() {
+    let mut repeater = Repeater::new(
+        RepeaterConfig::new(
+            "T1000003KV".parse().unwrap(),
+            MacAddress { address: [1; 6] },
+        ),
+        Duration::ZERO,
+    );
+    let message = ProbeStatusMessage {
+        probe_serial_number: SerialNumber { number: 0x10001ded },
+        status: test_status([Temperature::new(842); 8]),
+        network_information: NetworkInformation {
+            hop_count: Hops::One,
+        },
+    };
+    let request = || {
+        Request::new_with_id(
+            RequestMessage::ProbeStatusMessage(message.clone()),
+            0xa850cd42,
+        )
+    };
+
+    repeater.handle_request(request());
+    repeater.handle_request(request());
+
+    let forwarded = repeater.poll_transmit().unwrap();
+    assert_eq!(forwarded.request_header.request_id, 0xa850cd42);
+    assert_eq!(
+        forwarded.message,
+        RequestMessage::ProbeStatusMessage(ProbeStatusMessage {
+            network_information: NetworkInformation {
+                hop_count: Hops::Two
+            },
+            ..message
+        })
+    );
+    assert_eq!(
+        Request::try_from(forwarded.to_bytes().unwrap().as_slice()).unwrap(),
+        forwarded
+    );
+    assert_eq!(repeater.poll_transmit(), None);
+}
+
+#[test]
+fn test_heartbeat_lists_direct_probes() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut repeater = Repeater::new(
+        RepeaterConfig::new(
+            "T1000003KV".parse().unwrap(),
+            MacAddress { address: [1; 6] },
+        ),
+        Duration::ZERO,
+    );
+
+    repeater.handle_probe_status(
+        serial_number,
+        test_status([Temperature::new(842); 8]),
+        -60,
+        Duration::ZERO,
+    );
+    let status = repeater.poll_transmit().unwrap();
+    // Our own message coming back to us isn't forwarded again.
+    repeater.handle_request(status);
+    assert_eq!(repeater.poll_transmit(), None);
+
+    assert_eq!(repeater.poll_timeout(), Some(Duration::ZERO));
+    repeater.handle_timeout(Duration::ZERO);
+    assert_eq!(repeater.poll_timeout(), Some(Duration::from_secs(5)));
+
+    let Some(RequestMessage::HeartbeatMessage(heartbeat)) =
+        repeater.poll_transmit().map(|request| request.message)
+    else {
+        panic!("Expected a heartbeat");
+    };
+    assert_eq!(
+        heartbeat.connection_details[0].peer(),
+        Some(&DeviceSerial::Probe(serial_number))
+    );
+    assert_eq!(heartbeat.connection_details[0].rssi_dbm(), -60);
+    assert_eq!(heartbeat.connection_details[1].peer(), None);
+
+    let Some(RequestMessage::SyncThermometerList(list)) =
+        repeater.poll_transmit().map(|request| request.message)
+    else {
+        panic!("Expected a sync thermometer list");
+    };
+    assert!(list.contains(&serial_number));
+}
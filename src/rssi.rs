@@ -0,0 +1,402 @@
+//! Link quality history built from the RSSI in heartbeats and advertisements, plus packet loss
+//! estimated from gaps in log sequence numbers.
+
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec::Vec,
+};
+use core::time::Duration;
+
+use crate::uart::node::request::{HeartbeatMessage, ProbeStatusMessage};
+use crate::uart::node::response::ReadLogs;
+use crate::{DeviceSerial, ProbeStatus, SerialNumber};
+
+#[cfg(test)]
+use crate::uart::node::request::{Attributes, ConnectionDetailRecord, Direction};
+#[cfg(test)]
+use crate::{MacAddress, NodeSerialNumber, ProductType};
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// RSSI at or below this is treated as 0% quality.
+const RSSI_FLOOR: i16 = -100;
+/// RSSI at or above this is treated as 100% quality.
+const RSSI_CEILING: i16 = -50;
+
+/// How many of the sequence numbers counted as lost are remembered, so they can be credited back
+/// if they arrive late.
+const MAX_MISSING: usize = 256;
+/// A sequence number this far behind the last one means the probe started a new session, rather
+/// than a packet arriving late.
+const NEW_SESSION_GAP: u32 = 64;
+
+/// Converts an RSSI in dBm to a 0-100 quality score.
+pub fn signal_quality(rssi: f32) -> u8 {
+    let span = (RSSI_CEILING - RSSI_FLOOR) as f32;
+    let quality = (rssi - RSSI_FLOOR as f32) * 100.0 / span;
+    quality.clamp(0.0, 100.0) as u8
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LinkStats {
+    /// Most recent samples, oldest first.
+    pub history: VecDeque<(Duration, i8)>,
+    /// Exponentially weighted moving average of the RSSI.
+    pub smoothed_rssi: f32,
+    pub received: u32,
+    pub lost: u32,
+    /// Logs counted as lost that were later read back with ReadLogs. They still count towards
+    /// packet loss, since they never arrived live.
+    pub recovered: u32,
+    last_sequence_number: Option<u32>,
+    missing: BTreeSet<u32>,
+}
+
+impl LinkStats {
+    fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+            smoothed_rssi: 0.0,
+            received: 0,
+            lost: 0,
+            recovered: 0,
+            last_sequence_number: None,
+            missing: BTreeSet::new(),
+        }
+    }
+
+    pub fn last_rssi(&self) -> Option<i8> {
+        self.history.back().map(|(_, rssi)| *rssi)
+    }
+
+    pub fn last_seen(&self) -> Option<Duration> {
+        self.history.back().map(|(at, _)| *at)
+    }
+
+    /// Quality of the smoothed RSSI, or `None` if there have been no RSSI samples.
+    pub fn quality(&self) -> Option<u8> {
+        (!self.history.is_empty()).then(|| signal_quality(self.smoothed_rssi))
+    }
+
+    /// Fraction of sequence numbers that were never received, or `None` if there haven't been
+    /// any.
+    pub fn packet_loss(&self) -> Option<f32> {
+        let expected = self.received + self.lost;
+        (expected > 0).then(|| self.lost as f32 / expected as f32)
+    }
+
+    /// The most recent sequence numbers that were lost and haven't been read back since, oldest
+    /// first, e.g. to request with ReadLogs.
+    pub fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        self.missing.iter().copied()
+    }
+
+    fn record_rssi(&mut self, rssi: i8, now: Duration, smoothing: f32, history_length: usize) {
+        self.smoothed_rssi = if self.history.is_empty() {
+            rssi as f32
+        } else {
+            smoothing * rssi as f32 + (1.0 - smoothing) * self.smoothed_rssi
+        };
+
+        self.history.push_back((now, rssi));
+        while self.history.len() > history_length {
+            self.history.pop_front();
+        }
+    }
+
+    fn record_sequence_number(&mut self, sequence_number: u32) {
+        match self.last_sequence_number {
+            Some(last) if sequence_number == last => return,
+            Some(last) if sequence_number > last => {
+                let first_remembered = sequence_number.saturating_sub(MAX_MISSING as u32);
+                self.missing
+                    .extend((last + 1).max(first_remembered)..sequence_number);
+                self.lost += sequence_number - last - 1;
+            }
+            // Older than the last one, so it arrived late. It only counts if it was counted as
+            // lost, rather than being a duplicate.
+            Some(last) if last - sequence_number < NEW_SESSION_GAP => {
+                if self.missing.remove(&sequence_number) {
+                    self.lost -= 1;
+                    self.received += 1;
+                }
+                return;
+            }
+            Some(_) => self.missing.clear(),
+            None => {}
+        }
+        self.received += 1;
+        self.last_sequence_number = Some(sequence_number);
+        while self.missing.len() > MAX_MISSING {
+            self.missing.pop_first();
+        }
+    }
+
+    fn record_backfilled_sequence_number(&mut self, sequence_number: u32) {
+        if self.missing.remove(&sequence_number) {
+            self.recovered += 1;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WeakLink {
+    /// The other end of the link, or `None` for the link between the device and us.
+    pub peer: Option<DeviceSerial>,
+    pub smoothed_rssi: f32,
+    pub quality: Option<u8>,
+    pub packet_loss: Option<f32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WeakLinkThresholds {
+    /// Links with a quality below this are weak.
+    pub min_quality: u8,
+    /// Links losing more than this fraction of packets are weak.
+    pub max_packet_loss: f32,
+}
+
+impl Default for WeakLinkThresholds {
+    fn default() -> Self {
+        Self {
+            min_quality: 40,
+            max_packet_loss: 0.1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RssiTracker {
+    smoothing: f32,
+    history_length: usize,
+    links: BTreeMap<(DeviceSerial, DeviceSerial), LinkStats>,
+    direct: BTreeMap<DeviceSerial, LinkStats>,
+}
+
+impl Default for RssiTracker {
+    fn default() -> Self {
+        Self::new(0.2, 64)
+    }
+}
+
+impl RssiTracker {
+    /// `smoothing` is the weight given to each new sample (between 0 and 1), and
+    /// `history_length` how many raw samples are kept per link.
+    pub fn new(smoothing: f32, history_length: usize) -> Self {
+        Self {
+            smoothing: smoothing.clamp(0.0, 1.0),
+            history_length,
+            links: BTreeMap::new(),
+            direct: BTreeMap::new(),
+        }
+    }
+
+    /// Record the RSSI of every populated connection in a node's heartbeat.
+    pub fn record_heartbeat(&mut self, heartbeat: &HeartbeatMessage, now: Duration) {
+        let node = DeviceSerial::Node(heartbeat.node_serial_number);
+        for record in &heartbeat.connection_details {
+            if let Some(peer) = record.peer() {
+                self.links
+                    .entry((node, *peer))
+                    .or_insert_with(LinkStats::new)
+                    .record_rssi(record.rssi_dbm(), now, self.smoothing, self.history_length);
+            }
+        }
+    }
+
+    /// Record the RSSI our own radio measured for an advertisement from `serial_number`.
+    pub fn record_advertisement(&mut self, serial_number: DeviceSerial, rssi: i8, now: Duration) {
+        self.direct
+            .entry(serial_number)
+            .or_insert_with(LinkStats::new)
+            .record_rssi(rssi, now, self.smoothing, self.history_length);
+    }
+
+    /// Count a status notification from a direct connection towards the probe's packet loss.
+    pub fn record_status(&mut self, serial_number: SerialNumber, status: &ProbeStatus) {
+        self.record_sequence_number(serial_number.into(), status.log_end);
+    }
+
+    /// Count a status message relayed by MeatNet towards the probe's packet loss.
+    pub fn record_status_message(&mut self, message: &ProbeStatusMessage) {
+        self.record_sequence_number(message.probe_serial_number.into(), message.status.log_end);
+    }
+
+    /// Note a log read back from a node. Logs are read back long after they were sent, so they
+    /// don't count as received; they only mark the log as recovered if it had been lost.
+    pub fn record_read_logs(&mut self, read_logs: &ReadLogs) {
+        self.record_backfilled_sequence_number(
+            read_logs.probe_serial_number.into(),
+            read_logs.sequence_number,
+        );
+    }
+
+    /// Count a live sequence number, e.g. the log end of a status, towards packet loss.
+    pub fn record_sequence_number(&mut self, serial_number: DeviceSerial, sequence_number: u32) {
+        self.direct
+            .entry(serial_number)
+            .or_insert_with(LinkStats::new)
+            .record_sequence_number(sequence_number);
+    }
+
+    pub fn record_backfilled_sequence_number(
+        &mut self,
+        serial_number: DeviceSerial,
+        sequence_number: u32,
+    ) {
+        if let Some(stats) = self.direct.get_mut(&serial_number) {
+            stats.record_backfilled_sequence_number(sequence_number);
+        }
+    }
+
+    /// Stats for the link from `from` to `to` as reported in `from`'s heartbeats.
+    pub fn link(&self, from: &DeviceSerial, to: &DeviceSerial) -> Option<&LinkStats> {
+        self.links.get(&(*from, *to))
+    }
+
+    /// Stats for what we receive directly from `serial_number`.
+    pub fn direct(&self, serial_number: &DeviceSerial) -> Option<&LinkStats> {
+        self.direct.get(serial_number)
+    }
+
+    /// Every weak link, grouped by the device that reported it. Links between a device and us
+    /// are listed under the device with no peer.
+    pub fn weak_links(
+        &self,
+        thresholds: &WeakLinkThresholds,
+    ) -> BTreeMap<DeviceSerial, Vec<WeakLink>> {
+        let is_weak = |stats: &LinkStats| {
+            stats
+                .quality()
+                .is_some_and(|quality| quality < thresholds.min_quality)
+                || stats
+                    .packet_loss()
+                    .is_some_and(|loss| loss > thresholds.max_packet_loss)
+        };
+        let weak_link = |peer: Option<DeviceSerial>, stats: &LinkStats| WeakLink {
+            peer,
+            smoothed_rssi: stats.smoothed_rssi,
+            quality: stats.quality(),
+            packet_loss: stats.packet_loss(),
+        };
+
+        let mut report: BTreeMap<DeviceSerial, Vec<WeakLink>> = BTreeMap::new();
+        for (serial_number, stats) in self.direct.iter().filter(|(_, stats)| is_weak(stats)) {
+            report
+                .entry(*serial_number)
+                .or_default()
+                .push(weak_link(None, stats));
+        }
+        for ((from, to), stats) in self.links.iter().filter(|(_, stats)| is_weak(stats)) {
+            report
+                .entry(*from)
+                .or_default()
+                .push(weak_link(Some(*to), stats));
+        }
+        report
+    }
+}
+
+#[test]
+fn test_signal_quality() {
+    assert_eq!(signal_quality(-110.0), 0);
+    assert_eq!(signal_quality(-75.0), 50);
+    assert_eq!(signal_quality(-40.0), 100);
+}
+
+#[test]
+fn test_packet_loss_from_sequence_numbers() {
+    let probe = DeviceSerial::Probe(SerialNumber { number: 0x10001ded });
+    let mut tracker = RssiTracker::default();
+
+    for sequence_number in [1, 2, 2, 5, 4, 6, 10] {
+        tracker.record_sequence_number(probe, sequence_number);
+    }
+
+    // 3, 7, 8 and 9 never arrived.
+    let stats = tracker.direct(&probe).unwrap();
+    assert_eq!((stats.received, stats.lost), (6, 4));
+    assert_eq!(stats.packet_loss(), Some(0.4));
+}
+
+#[test]
+fn test_backfill_and_duplicates_dont_affect_live_loss() {
+    let probe = DeviceSerial::Probe(SerialNumber { number: 0x10001ded });
+    let mut tracker = RssiTracker::default();
+
+    tracker.record_sequence_number(probe, 100);
+    for sequence_number in 0..100 {
+        tracker.record_backfilled_sequence_number(probe, sequence_number);
+    }
+    tracker.record_sequence_number(probe, 101);
+    tracker.record_sequence_number(probe, 104);
+    // A duplicate of a packet that wasn't lost.
+    tracker.record_sequence_number(probe, 101);
+
+    let stats = tracker.direct(&probe).unwrap();
+    assert_eq!((stats.received, stats.lost), (3, 2));
+    assert_eq!(stats.missing().collect::<Vec<_>>(), vec![102, 103]);
+
+    tracker.record_sequence_number(probe, 102);
+    tracker.record_backfilled_sequence_number(probe, 103);
+    let stats = tracker.direct(&probe).unwrap();
+    assert_eq!((stats.received, stats.lost, stats.recovered), (4, 1, 1));
+    assert_eq!(stats.missing().count(), 0);
+
+    // A new session starts counting again.
+    tracker.record_sequence_number(probe, 0);
+    tracker.record_sequence_number(probe, 1);
+    let stats = tracker.direct(&probe).unwrap();
+    assert_eq!((stats.received, stats.lost), (6, 1));
+}
+
+#[test]
+fn test_weak_link_report() {
+    let node: NodeSerialNumber = "T1000003KV".parse().unwrap();
+    let strong = DeviceSerial::Probe(SerialNumber { number: 1 });
+    let weak = DeviceSerial::Probe(SerialNumber { number: 2 });
+    let record = |serial_number, rssi: Option<i8>| ConnectionDetailRecord {
+        serial_number,
+        product_type: ProductType::PredictiveProbe,
+        attributes: Attributes {
+            connection_detail_record_is_populated: rssi.is_some(),
+        },
+        rssi: rssi.unwrap_or(0) as u8,
+    };
+    let heartbeat = |strong_rssi, weak_rssi| HeartbeatMessage {
+        node_serial_number: node,
+        mac_address: MacAddress { address: [0; 6] },
+        product_type: ProductType::MeatNetRepeater,
+        hop_count: 0,
+        is_inbound: Direction::Inbound,
+        connection_details: [
+            record(strong, Some(strong_rssi)),
+            record(weak, Some(weak_rssi)),
+            record(strong, None),
+            record(weak, None),
+        ],
+    };
+
+    let mut tracker = RssiTracker::new(0.5, 2);
+    tracker.record_heartbeat(&heartbeat(-55, -80), Duration::from_secs(1));
+    tracker.record_heartbeat(&heartbeat(-60, -96), Duration::from_secs(2));
+    tracker.record_advertisement(strong, -70, Duration::from_secs(2));
+
+    let stats = tracker.link(&DeviceSerial::Node(node), &weak).unwrap();
+    assert_eq!(stats.history.len(), 2);
+    assert_eq!(stats.last_rssi(), Some(-96));
+
+    let report = tracker.weak_links(&WeakLinkThresholds::default());
+    assert_eq!(report.len(), 1);
+    assert_eq!(
+        report[&DeviceSerial::Node(node)]
+            .iter()
+            .map(|link| link.peer)
+            .collect::<Vec<_>>(),
+        vec![Some(weak)]
+    );
+}
@@ -0,0 +1,252 @@
+extern crate alloc;
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::{fmt, str::FromStr};
+use deku::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{ProductType, SerialNumber};
+
+#[cfg(test)]
+use alloc::string::ToString;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeSerialNumberError {
+    Empty,
+    TooLong(usize),
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for NodeSerialNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeSerialNumberError::Empty => write!(f, "serial number is empty"),
+            NodeSerialNumberError::TooLong(length) => write!(
+                f,
+                "serial number is {} characters, at most {} are allowed",
+                length,
+                NodeSerialNumber::LENGTH
+            ),
+            NodeSerialNumberError::InvalidCharacter(c) => {
+                write!(f, "serial number contains invalid character {:?}", c)
+            }
+        }
+    }
+}
+
+/// The 10 byte serial number used by MeatNet nodes and gauges, e.g. "T1000003KV".
+///
+/// The raw bytes are kept as received so that messages re-encode unchanged even when a device
+/// sends something that isn't a valid ASCII serial number.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, DekuRead, DekuWrite)]
+pub struct NodeSerialNumber {
+    bytes: [u8; 10],
+}
+
+impl NodeSerialNumber {
+    pub const LENGTH: usize = 10;
+
+    pub fn new(bytes: [u8; 10]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 10] {
+        &self.bytes
+    }
+
+    /// The serial number without its NUL padding, if it is one or more ASCII alphanumeric
+    /// characters.
+    pub fn as_str(&self) -> Option<&str> {
+        let length = self
+            .bytes
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(Self::LENGTH);
+        let (serial, padding) = self.bytes.split_at(length);
+
+        if length == 0
+            || !serial.iter().all(u8::is_ascii_alphanumeric)
+            || padding.iter().any(|b| *b != 0)
+        {
+            return None;
+        }
+        core::str::from_utf8(serial).ok()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.as_str().is_some()
+    }
+}
+
+impl FromStr for NodeSerialNumber {
+    type Err = NodeSerialNumberError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(NodeSerialNumberError::Empty);
+        }
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_alphanumeric()) {
+            return Err(NodeSerialNumberError::InvalidCharacter(c));
+        }
+        if s.len() > Self::LENGTH {
+            return Err(NodeSerialNumberError::TooLong(s.len()));
+        }
+
+        let mut bytes = [0; 10];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Self { bytes })
+    }
+}
+
+/// Valid serial numbers are shown as text, anything else as the hex of the raw bytes.
+impl fmt::Display for NodeSerialNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_str() {
+            Some(serial) => f.write_str(serial),
+            None => self.bytes.iter().try_for_each(|b| write!(f, "{:02X}", b)),
+        }
+    }
+}
+
+/// How a serial number is serialized: valid ones as text, anything else as the raw bytes, so
+/// everything a device might send survives a round trip.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SerializedNodeSerialNumber<'a> {
+    Serial(Cow<'a, str>),
+    Raw { raw: [u8; 10] },
+}
+
+impl Serialize for NodeSerialNumber {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Some(serial) => SerializedNodeSerialNumber::Serial(Cow::Borrowed(serial)),
+            None => SerializedNodeSerialNumber::Raw { raw: self.bytes },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeSerialNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match SerializedNodeSerialNumber::deserialize(deserializer)? {
+            SerializedNodeSerialNumber::Serial(serial) => serial.parse().map_err(de::Error::custom),
+            SerializedNodeSerialNumber::Raw { raw } => Ok(Self::new(raw)),
+        }
+    }
+}
+
+/// Identifies any device on MeatNet: probes by their 32 bit serial number, nodes and gauges by
+/// their 10 character one.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum DeviceSerial {
+    Probe(SerialNumber),
+    Node(NodeSerialNumber),
+}
+
+impl DeviceSerial {
+    /// Interpret a 10 byte serial number field using the product type sent alongside it. Probe
+    /// serial numbers occupy the first four bytes, little endian.
+    pub fn from_raw(raw: [u8; 10], product_type: &ProductType) -> Self {
+        match product_type {
+            ProductType::PredictiveProbe => DeviceSerial::Probe(SerialNumber {
+                number: u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]),
+            }),
+            _ => DeviceSerial::Node(NodeSerialNumber::new(raw)),
+        }
+    }
+
+    pub fn to_raw(&self) -> [u8; 10] {
+        match self {
+            DeviceSerial::Probe(serial_number) => {
+                let mut raw = [0; 10];
+                raw[..4].copy_from_slice(&serial_number.number.to_le_bytes());
+                raw
+            }
+            DeviceSerial::Node(serial_number) => *serial_number.as_bytes(),
+        }
+    }
+}
+
+impl From<SerialNumber> for DeviceSerial {
+    fn from(serial_number: SerialNumber) -> Self {
+        DeviceSerial::Probe(serial_number)
+    }
+}
+
+impl From<NodeSerialNumber> for DeviceSerial {
+    fn from(serial_number: NodeSerialNumber) -> Self {
+        DeviceSerial::Node(serial_number)
+    }
+}
+
+impl fmt::Display for DeviceSerial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSerial::Probe(serial_number) => serial_number.fmt(f),
+            DeviceSerial::Node(serial_number) => serial_number.fmt(f),
+        }
+    }
+}
+
+#[test]
+fn test_node_serial_number_parse_and_display() {
+    assert_eq!(
+        "T10000003KV".parse::<NodeSerialNumber>(),
+        Err(NodeSerialNumberError::TooLong(11))
+    );
+
+    let serial_number = NodeSerialNumber::from_bytes((b"T1000003KV".as_slice(), 0))
+        .unwrap()
+        .1;
+    assert_eq!(serial_number.to_string(), "T1000003KV");
+    assert_eq!("T1000003KV".parse(), Ok(serial_number));
+    assert_eq!(
+        "T1000-03KV".parse::<NodeSerialNumber>(),
+        Err(NodeSerialNumberError::InvalidCharacter('-'))
+    );
+
+    let short: NodeSerialNumber = "G12".parse().unwrap();
+    assert_eq!(short.as_bytes(), b"G12\0\0\0\0\0\0\0");
+    assert_eq!(short.to_string(), "G12");
+
+    let not_ascii = NodeSerialNumber::new([57, 15, 2, 0, 0, 0, 0, 0, 0, 0]);
+    assert!(!not_ascii.is_valid());
+    assert_eq!(not_ascii.to_string(), "390F0200000000000000");
+}
+
+#[test]
+fn test_device_serial_from_raw() {
+    let raw = [0xed, 0x1d, 0x00, 0x10, 0, 0, 0, 0, 0, 0];
+
+    let probe = DeviceSerial::from_raw(raw, &ProductType::PredictiveProbe);
+    assert_eq!(
+        probe,
+        DeviceSerial::Probe(SerialNumber { number: 0x10001ded })
+    );
+    assert_eq!(probe.to_string(), "10001DED");
+    assert_eq!(probe.to_raw(), raw);
+
+    let node = DeviceSerial::from_raw(*b"T1000003KV", &ProductType::MeatNetRepeater);
+    assert_eq!(node.to_string(), "T1000003KV");
+    assert_eq!(node.to_raw(), *b"T1000003KV");
+}
+
+#[test]
+fn test_node_serial_number_serde_round_trip() {
+    let valid: NodeSerialNumber = "T1000003KV".parse().unwrap();
+    let json = serde_json::to_string(&valid).unwrap();
+    assert_eq!(json, "\"T1000003KV\"");
+    assert_eq!(
+        serde_json::from_str::<NodeSerialNumber>(&json).unwrap(),
+        valid
+    );
+
+    // An empty connection record has a serial number of all zeros.
+    let empty = DeviceSerial::Node(NodeSerialNumber::new([0; 10]));
+    let json = serde_json::to_string(&empty).unwrap();
+    assert_eq!(json, "{\"Node\":{\"raw\":[0,0,0,0,0,0,0,0,0,0]}}");
+    assert_eq!(serde_json::from_str::<DeviceSerial>(&json).unwrap(), empty);
+}
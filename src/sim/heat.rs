@@ -0,0 +1,258 @@
+//! A physical model of a probe in a cooking piece of meat, for generating realistic readings.
+//!
+//! The meat is treated as a long cylinder, so heat only flows radially. Its temperature profile
+//! follows the 1-D heat equation with convection to the surrounding air at the surface, solved
+//! with explicit finite differences. The probe goes in through the side towards the middle, so
+//! each sensor's depth below the surface depends on how far the probe is inserted. Everything is
+//! plain arithmetic with a fixed step, so the same inputs always give the same readings.
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use core::time::Duration;
+
+use crate::sim::probe::TemperatureSource;
+use crate::temperature::Temperature;
+use crate::SENSOR_POSITIONS_MM;
+
+#[cfg(test)]
+use crate::temperature::IsTemperature;
+
+/// Number of points the meat's radius is divided into.
+const NODES: usize = 40;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Meat {
+    pub radius_mm: f64,
+    /// In m²/s.
+    pub thermal_diffusivity: f64,
+    /// In W/(m·K).
+    pub thermal_conductivity: f64,
+    /// In °C, the same all the way through.
+    pub initial_temperature: f64,
+}
+
+impl Meat {
+    /// Typical properties of lean beef straight from the fridge.
+    pub fn beef(radius_mm: f64) -> Self {
+        Self {
+            radius_mm,
+            thermal_diffusivity: 1.3e-7,
+            thermal_conductivity: 0.45,
+            initial_temperature: 4.0,
+        }
+    }
+}
+
+/// What's around the meat.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Environment {
+    /// In °C.
+    pub temperature: f64,
+    /// Combined convection and radiation, in W/(m²·K).
+    pub heat_transfer_coefficient: f64,
+}
+
+impl Environment {
+    pub fn oven(temperature: f64) -> Self {
+        Self {
+            temperature,
+            heat_transfer_coefficient: 15.0,
+        }
+    }
+
+    /// Still air, e.g. resting on the counter after coming out of the oven.
+    pub fn room(temperature: f64) -> Self {
+        Self {
+            temperature,
+            heat_transfer_coefficient: 8.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Event {
+    Environment(Environment),
+    /// How far the tip is pushed in, in millimetres. 0 takes the probe out.
+    InsertionDepth(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct HeatModel {
+    meat: Meat,
+    environment: Environment,
+    insertion_depth_mm: f64,
+    /// Temperatures from the centre (index 0) to the surface, in °C.
+    profile: Vec<f64>,
+    elapsed: Duration,
+    schedule: Vec<(Duration, Event)>,
+}
+
+impl HeatModel {
+    pub fn new(meat: Meat, environment: Environment, insertion_depth_mm: f64) -> Self {
+        Self {
+            meat,
+            environment,
+            insertion_depth_mm,
+            profile: vec![meat.initial_temperature; NODES + 1],
+            elapsed: Duration::ZERO,
+            schedule: Vec::new(),
+        }
+    }
+
+    /// Make something happen `at` after the start, e.g. taking the meat out of the oven.
+    pub fn schedule(&mut self, at: Duration, event: Event) {
+        let index = self.schedule.partition_point(|(other, _)| *other <= at);
+        self.schedule.insert(index, (at, event));
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Run the model forward, applying scheduled events as they come up.
+    pub fn advance_to(&mut self, elapsed: Duration) {
+        while self.elapsed < elapsed {
+            let until = match self.schedule.first() {
+                Some((at, _)) if *at <= elapsed => *at,
+                _ => elapsed,
+            };
+            self.simulate(until.saturating_sub(self.elapsed).as_secs_f64());
+            self.elapsed = self.elapsed.max(until);
+
+            while let Some((at, event)) = self.schedule.first() {
+                if *at > self.elapsed {
+                    break;
+                }
+                match *event {
+                    Event::Environment(environment) => self.environment = environment,
+                    Event::InsertionDepth(depth) => self.insertion_depth_mm = depth,
+                }
+                self.schedule.remove(0);
+            }
+        }
+    }
+
+    /// The temperature `depth_mm` below the surface, in °C.
+    pub fn temperature_at_depth(&self, depth_mm: f64) -> f64 {
+        let radius = self.meat.radius_mm;
+        if !(0.0..=2.0 * radius).contains(&depth_mm) {
+            return self.environment.temperature;
+        }
+
+        // Past the centre the probe is heading back out the other side.
+        let position = (radius - depth_mm).abs() / radius * NODES as f64;
+        let index = (position as usize).min(NODES - 1);
+        let fraction = position - index as f64;
+        self.profile[index] * (1.0 - fraction) + self.profile[index + 1] * fraction
+    }
+
+    pub fn center_temperature(&self) -> f64 {
+        self.profile[0]
+    }
+
+    /// What each of the probe's sensors reads. Sensors outside the meat read the air.
+    pub fn sensor_temperatures(&self) -> [Temperature; 8] {
+        SENSOR_POSITIONS_MM.map(|position| {
+            Temperature::from_celsius(
+                self.temperature_at_depth(self.insertion_depth_mm - position as f64) as f32,
+            )
+        })
+    }
+
+    fn simulate(&mut self, seconds: f64) {
+        let dr = self.meat.radius_mm / 1000.0 / NODES as f64;
+        let alpha = self.meat.thermal_diffusivity;
+        let biot = self.environment.heat_transfer_coefficient * dr / self.meat.thermal_conductivity;
+        // The explicit method is only stable for short enough steps, and the centre and surface
+        // nodes are the most demanding.
+        let max_step = 0.9 * dr * dr / (4.0 * alpha * (1.0 + biot));
+
+        let steps = (seconds / max_step) as u64 + 1;
+        let dt = seconds / steps as f64;
+        for _ in 0..steps {
+            self.step(dt, dr);
+        }
+    }
+
+    fn step(&mut self, dt: f64, dr: f64) {
+        let alpha = self.meat.thermal_diffusivity;
+        let old = self.profile.clone();
+
+        // At the centre the radial terms combine to twice the second derivative.
+        self.profile[0] = old[0] + dt * alpha * 4.0 * (old[1] - old[0]) / (dr * dr);
+
+        for i in 1..=NODES {
+            let outer = if i == NODES {
+                // A ghost point beyond the surface, set so the heat conducted out of the surface
+                // matches what convection carries away.
+                let flux = self.environment.heat_transfer_coefficient
+                    / self.meat.thermal_conductivity
+                    * (old[i] - self.environment.temperature);
+                old[i - 1] - 2.0 * dr * flux
+            } else {
+                old[i + 1]
+            };
+            let r = i as f64 * dr;
+            let laplacian = (outer - 2.0 * old[i] + old[i - 1]) / (dr * dr)
+                + (outer - old[i - 1]) / (2.0 * r * dr);
+            self.profile[i] = old[i] + dt * alpha * laplacian;
+        }
+    }
+}
+
+impl TemperatureSource for HeatModel {
+    fn temperatures(&mut self, elapsed: Duration) -> [Temperature; 8] {
+        self.advance_to(elapsed);
+        self.sensor_temperatures()
+    }
+}
+
+#[test]
+fn test_cooks_from_the_outside_in() {
+    let mut model = HeatModel::new(Meat::beef(35.0), Environment::oven(175.0), 70.0);
+    model.advance_to(Duration::from_secs(60 * 60));
+
+    let temperatures = model.sensor_temperatures().map(|t| t.get_celsius());
+    // T1 and T6 are at similar depths either side of the centre.
+    assert!(temperatures[0] > 4.0);
+    assert!(temperatures[2] < temperatures[0]);
+    assert!(temperatures[2] < temperatures[5]);
+    assert!(temperatures[5] < temperatures[6]);
+    assert_eq!(temperatures[7], 175.0);
+    assert!(model.center_temperature() > 30.0 && model.center_temperature() < 80.0);
+
+    // Deterministic.
+    let mut again = HeatModel::new(Meat::beef(35.0), Environment::oven(175.0), 70.0);
+    assert_eq!(
+        again.temperatures(Duration::from_secs(60 * 60)),
+        model.sensor_temperatures()
+    );
+}
+
+#[test]
+fn test_carryover_while_resting() {
+    let mut model = HeatModel::new(Meat::beef(35.0), Environment::oven(175.0), 35.0);
+    let removed_at = Duration::from_secs(60 * 60);
+    model.schedule(removed_at, Event::Environment(Environment::room(21.0)));
+    model.schedule(
+        removed_at + Duration::from_secs(30 * 60),
+        Event::InsertionDepth(0.0),
+    );
+
+    model.advance_to(removed_at);
+    let at_removal = model.center_temperature();
+    model.advance_to(removed_at + Duration::from_secs(10 * 60));
+    assert!(model.center_temperature() > at_removal);
+    assert_eq!(model.environment().temperature, 21.0);
+
+    model.advance_to(removed_at + Duration::from_secs(30 * 60));
+    assert_eq!(
+        model.sensor_temperatures(),
+        [Temperature::from_celsius(21.0); 8]
+    );
+}
@@ -0,0 +1,8 @@
+//! Simulated MeatNet devices for testing without hardware.
+//!
+//! Like [`crate::repeater`], these do no I/O of their own: they're driven by `handle_*` calls
+//! and a caller-supplied time.
+
+pub mod heat;
+pub mod node;
+pub mod probe;
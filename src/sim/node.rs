@@ -0,0 +1,292 @@
+//! A simulated MeatNet node, as seen from its UART.
+//!
+//! Bytes from the gateway go in through [`NodeEmulator::handle_input`] and bytes for the gateway
+//! come out of [`NodeEmulator::poll_transmit`], so it can sit on the other end of anything that
+//! carries a byte stream, such as one side of a PTY pair.
+
+extern crate alloc;
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::time::Duration;
+use deku::prelude::*;
+
+use crate::repeater::{Repeater, RepeaterConfig};
+use crate::sim::probe::{ProbeOutput, TemperatureSource, VirtualProbe};
+use crate::temperature::Temperature;
+use crate::uart::node::framing::FrameDecoder;
+use crate::uart::node::request::{Request, RequestMessage};
+use crate::uart::node::response::{self, Response, ResponseMessage};
+use crate::SerialNumber;
+
+#[cfg(test)]
+use crate::sim::probe::VirtualProbeConfig;
+#[cfg(test)]
+use crate::uart::node::request;
+#[cfg(test)]
+use crate::{EncapsulatableMessage, MacAddress};
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug)]
+struct ConnectedProbe<S: TemperatureSource> {
+    probe: VirtualProbe<S>,
+    rssi: i8,
+}
+
+#[derive(Debug)]
+pub struct NodeEmulator<S: TemperatureSource> {
+    repeater: Repeater,
+    probes: Vec<ConnectedProbe<S>>,
+    decoder: FrameDecoder,
+    transmit: VecDeque<Vec<u8>>,
+}
+
+impl<S: TemperatureSource> NodeEmulator<S> {
+    pub fn new(config: RepeaterConfig, now: Duration) -> Self {
+        Self {
+            repeater: Repeater::new(config, now),
+            probes: Vec::new(),
+            decoder: FrameDecoder::new(),
+            transmit: VecDeque::new(),
+        }
+    }
+
+    /// Connect a probe, which the node hears with the given RSSI.
+    pub fn add_probe(&mut self, probe: VirtualProbe<S>, rssi: i8) {
+        self.probes.push(ConnectedProbe { probe, rssi });
+    }
+
+    pub fn probe(&self, serial_number: &SerialNumber) -> Option<&VirtualProbe<S>> {
+        self.probes
+            .iter()
+            .map(|connected| &connected.probe)
+            .find(|probe| probe.serial_number() == *serial_number)
+    }
+
+    pub fn probe_mut(&mut self, serial_number: &SerialNumber) -> Option<&mut VirtualProbe<S>> {
+        self.probes
+            .iter_mut()
+            .map(|connected| &mut connected.probe)
+            .find(|probe| probe.serial_number() == *serial_number)
+    }
+
+    /// Bytes received from the gateway. They don't need to line up with frame boundaries.
+    pub fn handle_input(&mut self, bytes: &[u8]) {
+        self.decoder.push(bytes);
+        while let Some(frame) = self.decoder.next_frame() {
+            // Responses and anything we can't parse are ignored.
+            if let Ok(request) = Request::try_from(frame.as_slice()) {
+                self.handle_request(request);
+            }
+        }
+    }
+
+    /// Answer a request from the gateway. Messages that aren't requests for us are ignored.
+    pub fn handle_request(&mut self, request: Request) {
+        let request_id = request.request_header.request_id;
+        let responses: Vec<_> = match request.message {
+            RequestMessage::SetProbeId(message) => {
+                let probe = self.probe_mut(&message.probe_serial_number);
+                let success = probe.is_some();
+                if let Some(probe) = probe {
+                    probe.set_probe_id(message.probe_id);
+                }
+                Vec::from([(ResponseMessage::SetProbeId, success)])
+            }
+            RequestMessage::SetProbeColor(message) => {
+                let probe = self.probe_mut(&message.probe_serial_number);
+                let success = probe.is_some();
+                if let Some(probe) = probe {
+                    probe.set_color(message.color);
+                }
+                Vec::from([(ResponseMessage::SetProbeColor, success)])
+            }
+            RequestMessage::ReadSessionInformation(message) => {
+                let probe = self.probe(&message.serial_number);
+                let config = probe.map(|probe| probe.config());
+                let information = response::ReadSessionInformation {
+                    probe_serial_number: message.serial_number,
+                    probe_session_id: config.map_or(0, |config| config.session_id),
+                    probe_sample_period: config
+                        .map_or(0, |config| config.sample_period.as_millis() as u16),
+                };
+                Vec::from([(
+                    ResponseMessage::ReadSessionInformation(information),
+                    probe.is_some(),
+                )])
+            }
+            RequestMessage::ReadLogs(message) => match self.probe(&message.probe_serial_number) {
+                Some(probe) => probe
+                    .log_range(message.sequence_number_start, message.sequence_number_end)
+                    .map(|entry| {
+                        let read_logs = response::ReadLogs {
+                            probe_serial_number: message.probe_serial_number,
+                            sequence_number: entry.sequence_number,
+                            temperatures: entry.temperatures,
+                            estimated_core_temperature_bit: 0,
+                            virtual_ambient_sensor: entry.virtual_ambient_sensor,
+                            virtual_surface_sensor: entry.virtual_surface_sensor,
+                            virtual_core_sensor: entry.virtual_core_sensor,
+                            virtual_sensors_and_state: [0; 6],
+                        };
+                        (ResponseMessage::ReadLogs(read_logs), true)
+                    })
+                    .collect(),
+                None => {
+                    let read_logs = response::ReadLogs {
+                        probe_serial_number: message.probe_serial_number,
+                        sequence_number: message.sequence_number_start,
+                        temperatures: [Temperature::new(0); 8],
+                        estimated_core_temperature_bit: 0,
+                        virtual_ambient_sensor: 0,
+                        virtual_surface_sensor: 0,
+                        virtual_core_sensor: 0,
+                        virtual_sensors_and_state: [0; 6],
+                    };
+                    Vec::from([(ResponseMessage::ReadLogs(read_logs), false)])
+                }
+            },
+            _ => return,
+        };
+
+        for (message, success) in responses {
+            let response = Response::new(message, request_id, success);
+            if let Ok(bytes) = response.to_bytes() {
+                self.transmit.push_back(bytes);
+            }
+        }
+    }
+
+    /// When [`NodeEmulator::handle_timeout`] next needs calling.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        self.probes
+            .iter()
+            .filter_map(|connected| connected.probe.poll_timeout())
+            .chain(self.repeater.poll_timeout())
+            .min()
+    }
+
+    /// Runs the probes and queues their status messages, plus heartbeats when they're due.
+    pub fn handle_timeout(&mut self, now: Duration) {
+        for connected in &mut self.probes {
+            connected.probe.handle_timeout(now);
+            while let Some(output) = connected.probe.poll_output() {
+                if let ProbeOutput::Status(status) = output {
+                    self.repeater.handle_probe_status(
+                        connected.probe.serial_number(),
+                        status,
+                        connected.rssi,
+                        now,
+                    );
+                }
+            }
+        }
+
+        self.repeater.handle_timeout(now);
+        while let Some(request) = self.repeater.poll_transmit() {
+            // The thermometer list is only for other nodes.
+            if matches!(request.message, RequestMessage::SyncThermometerList(_)) {
+                continue;
+            }
+            if let Ok(bytes) = request.to_bytes() {
+                self.transmit.push_back(bytes);
+            }
+        }
+    }
+
+    /// The next frame to send to the gateway.
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_node() -> NodeEmulator<impl TemperatureSource> {
+    let mut node = NodeEmulator::new(
+        RepeaterConfig::new(
+            "T1000003KV".parse().unwrap(),
+            MacAddress { address: [1; 6] },
+        ),
+        Duration::ZERO,
+    );
+    let config = VirtualProbeConfig {
+        session_id: 0x22f5febc,
+        ..VirtualProbeConfig::new(SerialNumber { number: 0x10001ded })
+    };
+    node.add_probe(
+        VirtualProbe::new(config, |_| [Temperature::new(842); 8], Duration::ZERO),
+        -60,
+    );
+    node
+}
+
+#[test]
+fn test_node_emulator_answers_requests() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut node = test_node();
+    node.handle_timeout(Duration::from_secs(20));
+    while node.poll_transmit().is_some() {}
+
+    let request = request::ReadSessionInformation { serial_number }.encapsulate();
+    let request_id = request.request_header.request_id;
+    let bytes = request.to_bytes().unwrap();
+    // Split across two reads, as a serial port might deliver it.
+    node.handle_input(&bytes[..4]);
+    node.handle_input(&bytes[4..]);
+
+    let response = Response::try_from(node.poll_transmit().unwrap().as_slice()).unwrap();
+    assert_eq!(response.header.request_id, request_id);
+    assert!(response.header.success);
+    assert_eq!(
+        response.message,
+        ResponseMessage::ReadSessionInformation(response::ReadSessionInformation {
+            probe_serial_number: serial_number,
+            probe_session_id: 0x22f5febc,
+            probe_sample_period: 5000,
+        })
+    );
+
+    let request = request::ReadLogs {
+        probe_serial_number: serial_number,
+        sequence_number_start: 2,
+        sequence_number_end: 10,
+    }
+    .encapsulate();
+    node.handle_input(&request.to_bytes().unwrap());
+    let sequence_numbers: Vec<_> = core::iter::from_fn(|| node.poll_transmit())
+        .map(
+            |bytes| match Response::try_from(bytes.as_slice()).unwrap().message {
+                ResponseMessage::ReadLogs(read_logs) => read_logs.sequence_number,
+                message => panic!("Unexpected response {message:?}"),
+            },
+        )
+        .collect();
+    assert_eq!(sequence_numbers, [2, 3, 4]);
+
+    let request = request::SetProbeId {
+        probe_serial_number: SerialNumber { number: 1 },
+        probe_id: 3,
+    }
+    .encapsulate();
+    node.handle_input(&request.to_bytes().unwrap());
+    let response = Response::try_from(node.poll_transmit().unwrap().as_slice()).unwrap();
+    assert!(!response.header.success);
+}
+
+#[test]
+fn test_node_emulator_sends_status_and_heartbeats() {
+    let mut node = test_node();
+    node.handle_timeout(Duration::ZERO);
+
+    let messages: Vec<_> = core::iter::from_fn(|| node.poll_transmit())
+        .map(|bytes| Request::try_from(bytes.as_slice()).unwrap().message)
+        .collect();
+    assert!(matches!(
+        messages.as_slice(),
+        [
+            RequestMessage::ProbeStatusMessage(_),
+            RequestMessage::HeartbeatMessage(_)
+        ]
+    ));
+    assert_eq!(node.poll_timeout(), Some(Duration::from_millis(250)));
+}
@@ -0,0 +1,390 @@
+//! A simulated Predictive Probe.
+
+extern crate alloc;
+
+use alloc::{collections::VecDeque, vec::Vec};
+use core::time::Duration;
+use deku::prelude::*;
+
+use crate::analysis::virtual_sensors::VirtualSensors;
+use crate::temperature::Temperature;
+use crate::uart::probe::{request, response};
+use crate::{
+    BatteryStatus, Color, ManufacturerSpecificData, Mode, ProbeStatus, ProductType, SerialNumber,
+};
+
+#[cfg(test)]
+use crate::EncapsulatableMessage;
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// Where a virtual probe's sensor readings come from.
+pub trait TemperatureSource {
+    /// The 8 sensor temperatures, `elapsed` after the probe was started.
+    fn temperatures(&mut self, elapsed: Duration) -> [Temperature; 8];
+}
+
+impl<F: FnMut(Duration) -> [Temperature; 8]> TemperatureSource for F {
+    fn temperatures(&mut self, elapsed: Duration) -> [Temperature; 8] {
+        self(elapsed)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VirtualProbeConfig {
+    pub serial_number: SerialNumber,
+    pub probe_id: u8,
+    pub color: Color,
+    pub mode: Mode,
+    pub session_id: u32,
+    pub sample_period: Duration,
+    pub advertising_interval: Duration,
+    pub status_interval: Duration,
+    /// The oldest log entries are dropped once there are this many.
+    pub log_capacity: usize,
+}
+
+impl VirtualProbeConfig {
+    pub fn new(serial_number: SerialNumber) -> Self {
+        Self {
+            serial_number,
+            probe_id: 0,
+            color: Color::Yellow,
+            mode: Mode::Normal,
+            session_id: 0,
+            sample_period: Duration::from_secs(5),
+            advertising_interval: Duration::from_millis(250),
+            status_interval: Duration::from_secs(1),
+            log_capacity: 4096,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LogEntry {
+    pub sequence_number: u32,
+    /// Samples taken in Instant Read mode don't have meaningful virtual sensors.
+    pub mode: Mode,
+    pub temperatures: [Temperature; 8],
+    pub virtual_core_sensor: u8,
+    pub virtual_surface_sensor: u8,
+    pub virtual_ambient_sensor: u8,
+}
+
+impl LogEntry {
+    /// The first byte of the virtual sensors and state field in a log response.
+    fn virtual_sensors_byte(&self) -> u8 {
+        (self.virtual_ambient_sensor & 0b11) << 5
+            | (self.virtual_surface_sensor & 0b11) << 3
+            | (self.virtual_core_sensor & 0b111)
+    }
+}
+
+/// Something the probe sends without being asked.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProbeOutput {
+    Advertisement(ManufacturerSpecificData),
+    Status(ProbeStatus),
+}
+
+#[derive(Debug)]
+pub struct VirtualProbe<S: TemperatureSource> {
+    config: VirtualProbeConfig,
+    source: S,
+    started_at: Duration,
+    current: LogEntry,
+    log: VecDeque<LogEntry>,
+    next_sample: Duration,
+    next_advertisement: Duration,
+    next_status: Duration,
+    output: VecDeque<ProbeOutput>,
+}
+
+impl<S: TemperatureSource> VirtualProbe<S> {
+    /// Takes the first sample straight away, so the probe has readings from the start.
+    pub fn new(config: VirtualProbeConfig, mut source: S, now: Duration) -> Self {
+        let current = sample(0, config.mode, source.temperatures(Duration::ZERO));
+        let mut probe = Self {
+            source,
+            started_at: now,
+            log: VecDeque::from([current.clone()]),
+            current,
+            next_sample: now + config.sample_period,
+            next_advertisement: now,
+            next_status: now,
+            output: VecDeque::new(),
+            config,
+        };
+        probe.handle_timeout(now);
+        probe
+    }
+
+    pub fn config(&self) -> &VirtualProbeConfig {
+        &self.config
+    }
+
+    pub fn serial_number(&self) -> SerialNumber {
+        self.config.serial_number
+    }
+
+    pub fn set_probe_id(&mut self, probe_id: u8) {
+        self.config.probe_id = probe_id & 0b111;
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.config.color = color;
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.config.mode = mode;
+    }
+
+    /// The most recent sample.
+    pub fn current(&self) -> &LogEntry {
+        &self.current
+    }
+
+    pub fn log(&self) -> impl Iterator<Item = &LogEntry> {
+        self.log.iter()
+    }
+
+    /// Logged samples with sequence numbers from `start` to `end` inclusive.
+    pub fn log_range(&self, start: u32, end: u32) -> impl Iterator<Item = &LogEntry> {
+        self.log
+            .iter()
+            .filter(move |entry| (start..=end).contains(&entry.sequence_number))
+    }
+
+    /// When [`VirtualProbe::handle_timeout`] next needs calling.
+    pub fn poll_timeout(&self) -> Option<Duration> {
+        Some(
+            self.next_sample
+                .min(self.next_advertisement)
+                .min(self.next_status),
+        )
+    }
+
+    pub fn handle_timeout(&mut self, now: Duration) {
+        while self.next_sample <= now {
+            let elapsed = self.next_sample.saturating_sub(self.started_at);
+            self.current = sample(
+                self.current.sequence_number + 1,
+                self.config.mode,
+                self.source.temperatures(elapsed),
+            );
+            self.log.push_back(self.current.clone());
+            while self.log.len() > self.config.log_capacity {
+                self.log.pop_front();
+            }
+            self.next_sample += self.config.sample_period;
+        }
+
+        if self.next_advertisement <= now {
+            self.output
+                .push_back(ProbeOutput::Advertisement(self.advertisement()));
+            self.next_advertisement = now + self.config.advertising_interval;
+        }
+        if self.next_status <= now {
+            self.output.push_back(ProbeOutput::Status(self.status()));
+            self.next_status = now + self.config.status_interval;
+        }
+    }
+
+    pub fn poll_output(&mut self) -> Option<ProbeOutput> {
+        self.output.pop_front()
+    }
+
+    pub fn advertisement(&self) -> ManufacturerSpecificData {
+        ManufacturerSpecificData {
+            product_type: ProductType::PredictiveProbe,
+            probe_serial_number: self.config.serial_number,
+            temperatures: self.current.temperatures,
+            probe_id: self.config.probe_id,
+            color: self.config.color,
+            mode: self.config.mode,
+            virtual_ambient_sensor: self.current.virtual_ambient_sensor,
+            virtual_surface_sensor: self.current.virtual_surface_sensor,
+            virtual_core_sensor: self.current.virtual_core_sensor,
+            battery_status: BatteryStatus::Ok,
+            network_information: None,
+        }
+    }
+
+    pub fn status(&self) -> ProbeStatus {
+        ProbeStatus {
+            log_start: self.log.front().map_or(0, |entry| entry.sequence_number),
+            log_end: self.current.sequence_number,
+            temperatures: self.current.temperatures,
+            probe_id: self.config.probe_id,
+            color: self.config.color,
+            mode: self.config.mode,
+            virtual_ambient_sensor: self.current.virtual_ambient_sensor,
+            virtual_surface_sensor: self.current.virtual_surface_sensor,
+            virtual_core_sensor: self.current.virtual_core_sensor,
+            battery_status: BatteryStatus::Ok,
+            prediction_status: [0; 7],
+            food_safe_data: [0; 10],
+            food_safe_status: [0; 8],
+        }
+    }
+
+    /// Answer a request received over the probe's UART. Reading logs gets a response per
+    /// logged sample in the range.
+    pub fn handle_uart_request(&mut self, request: &request::Request) -> Vec<response::Response> {
+        let message = match &request.message {
+            request::RequestType::SetProbeId(set_probe_id) => {
+                self.set_probe_id(set_probe_id.probe_id);
+                response::ResponseMessage::SetProbeId(response::SetProbeId {})
+            }
+            request::RequestType::SetProbeColor(set_probe_color) => {
+                self.set_color(set_probe_color.color);
+                response::ResponseMessage::SetProbeColor(response::SetProbeColor {})
+            }
+            request::RequestType::ReadSessionInformation(_) => {
+                response::ResponseMessage::ReadSessionInformation(
+                    response::ReadSessionInformation {
+                        probe_session_id: self.config.session_id,
+                        probe_sample_period: self.config.sample_period.as_millis() as u16,
+                    },
+                )
+            }
+            request::RequestType::ReadLogs(read_logs) => {
+                return self
+                    .log_range(
+                        read_logs.sequence_number_start,
+                        read_logs.sequence_number_end,
+                    )
+                    .map(|entry| {
+                        let mut virtual_sensors_and_state = [0; 7];
+                        virtual_sensors_and_state[0] = entry.virtual_sensors_byte();
+                        response::Response::new(
+                            response::ResponseMessage::ReadLogs(response::ReadLogs {
+                                sequence_number: entry.sequence_number,
+                                temperatures: entry.temperatures,
+                                virtual_sensors_and_state,
+                            }),
+                            true,
+                        )
+                    })
+                    .collect();
+            }
+        };
+
+        Vec::from([response::Response::new(message, true)])
+    }
+
+    /// Parse a request frame and return the encoded responses.
+    pub fn handle_uart_bytes(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, DekuError> {
+        let (_, request) = request::Request::from_bytes((bytes, 0))?;
+        self.handle_uart_request(&request)
+            .iter()
+            .map(|response| response.to_bytes())
+            .collect()
+    }
+}
+
+fn sample(sequence_number: u32, mode: Mode, temperatures: [Temperature; 8]) -> LogEntry {
+    let virtual_sensors = match mode {
+        Mode::InstantRead => VirtualSensors::default(),
+        _ => VirtualSensors::select(&temperatures),
+    };
+
+    LogEntry {
+        sequence_number,
+        mode,
+        temperatures,
+        virtual_core_sensor: virtual_sensors.core,
+        virtual_surface_sensor: virtual_sensors.surface,
+        virtual_ambient_sensor: virtual_sensors.ambient,
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_probe() -> VirtualProbe<impl TemperatureSource> {
+    let config = VirtualProbeConfig {
+        session_id: 0x22f5febc,
+        ..VirtualProbeConfig::new(SerialNumber { number: 0x10001ded })
+    };
+    // Warms by one raw step (0.05°C) per second.
+    let source = |elapsed: Duration| {
+        let step = elapsed.as_secs() as u16;
+        [
+            Temperature::new(850 + step),
+            Temperature::new(842 + step),
+            Temperature::new(843 + step),
+            Temperature::new(843 + step),
+            Temperature::new(851 + step),
+            Temperature::new(853 + step),
+            Temperature::new(853 + step),
+            Temperature::new(856 + step),
+        ]
+    };
+
+    VirtualProbe::new(config, source, Duration::ZERO)
+}
+
+#[test]
+fn test_virtual_probe_outputs() {
+    let mut probe = test_probe();
+
+    let Some(ProbeOutput::Advertisement(advertisement)) = probe.poll_output() else {
+        panic!("Expected an advertisement");
+    };
+    let bytes = advertisement.to_bytes().unwrap();
+    assert_eq!(
+        ManufacturerSpecificData::from_bytes((bytes.as_slice(), 0))
+            .unwrap()
+            .1,
+        advertisement
+    );
+    assert_eq!(*advertisement.get_core_temperature(), Temperature::new(842));
+    assert!(matches!(probe.poll_output(), Some(ProbeOutput::Status(_))));
+    assert_eq!(probe.poll_output(), None);
+
+    probe.handle_timeout(Duration::from_secs(10));
+    assert_eq!(probe.poll_timeout(), Some(Duration::from_millis(10250)));
+
+    let status = probe.status();
+    assert_eq!((status.log_start, status.log_end), (0, 2));
+    assert_eq!(*status.get_core_temperature(), Temperature::new(852));
+}
+
+#[test]
+fn test_virtual_probe_uart() {
+    let mut probe = test_probe();
+    probe.handle_timeout(Duration::from_secs(10));
+
+    let responses = probe
+        .handle_uart_bytes(
+            &request::ReadSessionInformation {}
+                .encapsulate()
+                .to_bytes()
+                .unwrap(),
+        )
+        .unwrap();
+    assert_eq!(
+        responses,
+        vec![vec![
+            202, 254, 188, 168, 3, 1, 6, 188, 254, 245, 34, 136, 19
+        ]]
+    );
+
+    let responses = probe.handle_uart_request(
+        &request::ReadLogs {
+            sequence_number_start: 1,
+            sequence_number_end: 5,
+        }
+        .encapsulate(),
+    );
+    assert_eq!(responses.len(), 2);
+    let response::ResponseMessage::ReadLogs(read_logs) = &responses[1].message else {
+        panic!("Expected a log response");
+    };
+    assert_eq!(read_logs.sequence_number, 2);
+    assert_eq!(read_logs.temperatures[1], Temperature::new(852));
+
+    probe.handle_uart_request(&request::SetProbeId { probe_id: 5 }.encapsulate());
+    assert_eq!(probe.advertisement().probe_id, 5);
+}
@@ -1,6 +1,18 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bitvec::prelude::*;
+use core::fmt;
+use core::ops::{Add, Neg, Sub};
+use core::time::Duration;
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
 
+#[cfg(test)]
+use alloc::format;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
 pub trait IsTemperature {
     fn get_celsius(&self) -> f32;
 
@@ -9,19 +21,73 @@ pub trait IsTemperature {
     }
 }
 
-#[derive(Debug, PartialEq, DekuRead, Clone, Copy, Serialize, Deserialize)]
+/// Convert to a raw value counting `step`s from `offset`, clamped to what fits in `max_raw`.
+fn raw_from_celsius(celsius: f32, offset: f32, step: f32, max_raw: u16) -> u16 {
+    let steps = (celsius - offset) / step;
+    // NaN ends up as 0.
+    (steps.clamp(0.0, max_raw as f32) + 0.5) as u16
+}
+
+fn fahrenheit_to_celsius(fahrenheit: f32) -> f32 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Copy, Serialize, Deserialize)]
 pub struct Temperature {
     raw_value: u16,
 }
 
 impl Temperature {
+    /// The largest raw value, 13 bits.
+    pub const MAX_RAW: u16 = 0x1fff;
+
     pub fn new(raw_value: u16) -> Self {
         Temperature { raw_value }
     }
 
+    /// Rounded to the nearest 0.05°C, and clamped to the -20°C to 389.55°C the probe can send.
+    pub fn from_celsius(celsius: f32) -> Self {
+        Self::new(raw_from_celsius(celsius, -20.0, 0.05, Self::MAX_RAW))
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Self::from_celsius(fahrenheit_to_celsius(fahrenheit))
+    }
+
     pub fn get_raw_value(&self) -> u16 {
         self.raw_value
     }
+
+    /// Raw values at either end of the range aren't real readings: the probe sends them for
+    /// sensors that are disconnected, shorted or out of range.
+    pub fn get_reading_status(&self) -> ReadingStatus {
+        match self.raw_value {
+            0 => ReadingStatus::Underrange,
+            Self::MAX_RAW.. => ReadingStatus::Overrange,
+            _ => ReadingStatus::Valid,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.get_reading_status() == ReadingStatus::Valid
+    }
+
+    /// `None` if this isn't a real reading.
+    pub fn valid(&self) -> Option<Temperature> {
+        self.is_valid().then_some(*self)
+    }
+}
+
+/// Whether a sensor's raw value is a real temperature.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum ReadingStatus {
+    Valid,
+    /// At the bottom of the range, -20°C.
+    Underrange,
+    /// At the top of the range, 389.55°C.
+    Overrange,
+    /// The probe reported itself as errored, so none of its readings can be trusted.
+    Errored,
 }
 
 impl IsTemperature for Temperature {
@@ -34,16 +100,50 @@ impl IsTemperature for Temperature {
     }
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+/// Pack 8 temperatures into 13 bytes, 13 bits each, least significant bit first.
+pub fn pack_temperatures(temperatures: &[Temperature; 8]) -> [u8; 13] {
+    let mut bits = bitarr![u8, Lsb0; 0; 8 * 13];
+    for (chunk, temperature) in bits.chunks_mut(13).zip(temperatures) {
+        chunk.store_le(temperature.get_raw_value() & Temperature::MAX_RAW);
+    }
+    bits.into_inner()
+}
+
+/// The inverse of [`pack_temperatures`].
+pub fn unpack_temperatures(bytes: [u8; 13]) -> [Temperature; 8] {
+    let bits = bytes.into_bitarray::<Lsb0>();
+    let mut temperatures = [Temperature::new(0); 8];
+    for (temperature, chunk) in temperatures.iter_mut().zip(bits.chunks(13)) {
+        *temperature = Temperature::new(chunk.load_le());
+    }
+    temperatures
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct CoreTemperature {
     #[deku(bits = "11", endian = "little")]
     raw_value: u16,
 }
 
 impl CoreTemperature {
+    pub const MAX_RAW: u16 = 0x7ff;
+
     pub fn new(raw_value: u16) -> Self {
         Self { raw_value }
     }
+
+    /// Rounded to the nearest 0.1°C, and clamped to -20°C to 184.7°C.
+    pub fn from_celsius(celsius: f32) -> Self {
+        Self::new(raw_from_celsius(celsius, -20.0, 0.1, Self::MAX_RAW))
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Self::from_celsius(fahrenheit_to_celsius(fahrenheit))
+    }
+
+    pub fn get_raw_value(&self) -> u16 {
+        self.raw_value
+    }
 }
 
 impl IsTemperature for CoreTemperature {
@@ -52,16 +152,31 @@ impl IsTemperature for CoreTemperature {
     }
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct PredictionSetPointTemperature {
     #[deku(bits = "10", endian = "little")]
     raw_value: u16,
 }
 
 impl PredictionSetPointTemperature {
+    pub const MAX_RAW: u16 = 0x3ff;
+
     pub fn new(raw_value: u16) -> Self {
         Self { raw_value }
     }
+
+    /// Rounded to the nearest 0.1°C, and clamped to 0°C to 102.3°C.
+    pub fn from_celsius(celsius: f32) -> Self {
+        Self::new(raw_from_celsius(celsius, 0.0, 0.1, Self::MAX_RAW))
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Self::from_celsius(fahrenheit_to_celsius(fahrenheit))
+    }
+
+    pub fn get_raw_value(&self) -> u16 {
+        self.raw_value
+    }
 }
 
 impl IsTemperature for PredictionSetPointTemperature {
@@ -70,16 +185,31 @@ impl IsTemperature for PredictionSetPointTemperature {
     }
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct HeatStartTemperature {
     #[deku(bits = "10", endian = "little")]
     raw_value: u16,
 }
 
 impl HeatStartTemperature {
+    pub const MAX_RAW: u16 = 0x3ff;
+
     pub fn new(raw_value: u16) -> Self {
         Self { raw_value }
     }
+
+    /// Rounded to the nearest 0.1°C, and clamped to 0°C to 102.3°C.
+    pub fn from_celsius(celsius: f32) -> Self {
+        Self::new(raw_from_celsius(celsius, 0.0, 0.1, Self::MAX_RAW))
+    }
+
+    pub fn from_fahrenheit(fahrenheit: f32) -> Self {
+        Self::from_celsius(fahrenheit_to_celsius(fahrenheit))
+    }
+
+    pub fn get_raw_value(&self) -> u16 {
+        self.raw_value
+    }
 }
 
 impl IsTemperature for HeatStartTemperature {
@@ -87,3 +217,377 @@ impl IsTemperature for HeatStartTemperature {
         self.raw_value as f32 * 0.1
     }
 }
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl TemperatureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+            TemperatureUnit::Kelvin => "K",
+        }
+    }
+}
+
+/// Divide, rounding halves away from zero.
+fn div_round(numerator: i64, denominator: i64) -> i64 {
+    let half = denominator / 2;
+    if (numerator < 0) != (denominator < 0) {
+        (numerator - half) / denominator
+    } else {
+        (numerator + half) / denominator
+    }
+}
+
+/// Writes thousandths as a decimal, to the formatter's precision or 2 places by default.
+fn format_thousandths(
+    f: &mut fmt::Formatter<'_>,
+    thousandths: i64,
+    unit: TemperatureUnit,
+) -> fmt::Result {
+    let places = f.precision().unwrap_or(2).min(3);
+    let scale = 10i64.pow(3 - places as u32);
+    let value = div_round(thousandths, scale);
+    let divisor = 10i64.pow(places as u32);
+
+    let sign = if value < 0 { "-" } else { "" };
+    let (whole, fraction) = (value.abs() / divisor, value.abs() % divisor);
+    if places == 0 {
+        write!(f, "{sign}{whole} {}", unit.symbol())
+    } else {
+        write!(f, "{sign}{whole}.{fraction:0places$} {}", unit.symbol())
+    }
+}
+
+/// A temperature as an exact number of thousandths of a degree Celsius. Every raw temperature
+/// the probe sends converts to this without rounding.
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct MilliCelsius(i32);
+
+impl MilliCelsius {
+    pub const fn new(millidegrees: i32) -> Self {
+        Self(millidegrees)
+    }
+
+    pub const fn from_degrees(degrees: i32) -> Self {
+        Self(degrees * 1000)
+    }
+
+    pub fn millidegrees(&self) -> i32 {
+        self.0
+    }
+
+    /// Thousandths of a degree in `unit`. Only Fahrenheit can need rounding.
+    pub fn in_unit(&self, unit: TemperatureUnit) -> i64 {
+        let millidegrees = self.0 as i64;
+        match unit {
+            TemperatureUnit::Celsius => millidegrees,
+            TemperatureUnit::Fahrenheit => div_round(millidegrees * 9, 5) + 32_000,
+            TemperatureUnit::Kelvin => millidegrees + 273_150,
+        }
+    }
+
+    /// For when a float is needed, e.g. for plotting.
+    pub fn to_celsius(&self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+
+    /// The mean of `temperatures`, or `None` if there aren't any.
+    pub fn average(temperatures: impl IntoIterator<Item = MilliCelsius>) -> Option<Self> {
+        let (sum, count) = temperatures
+            .into_iter()
+            .fold((0i64, 0i64), |(sum, count), temperature| {
+                (sum + temperature.0 as i64, count + 1)
+            });
+        (count > 0).then(|| Self(div_round(sum, count) as i32))
+    }
+
+    pub fn display(&self, unit: TemperatureUnit) -> impl fmt::Display {
+        Displayed {
+            thousandths: self.in_unit(unit),
+            unit,
+        }
+    }
+}
+
+impl fmt::Display for MilliCelsius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_thousandths(f, self.0 as i64, TemperatureUnit::Celsius)
+    }
+}
+
+/// A difference between two temperatures, in thousandths of a degree Celsius.
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct TemperatureDelta(i32);
+
+impl TemperatureDelta {
+    pub const fn new(millidegrees: i32) -> Self {
+        Self(millidegrees)
+    }
+
+    pub fn millidegrees(&self) -> i32 {
+        self.0
+    }
+
+    pub fn abs(&self) -> Self {
+        Self(self.0.abs())
+    }
+
+    /// Thousandths of a degree in `unit`. Kelvin and Celsius degrees are the same size.
+    pub fn in_unit(&self, unit: TemperatureUnit) -> i64 {
+        match unit {
+            TemperatureUnit::Celsius | TemperatureUnit::Kelvin => self.0 as i64,
+            TemperatureUnit::Fahrenheit => div_round(self.0 as i64 * 9, 5),
+        }
+    }
+
+    /// The change there would be over `per` at the rate of this change over `over`, e.g. degrees
+    /// per minute. `None` if `over` is zero.
+    pub fn rate(&self, over: Duration, per: Duration) -> Option<Self> {
+        let over = over.as_nanos() as i128;
+        (over > 0).then(|| {
+            let scaled = self.0 as i128 * per.as_nanos() as i128;
+            let half = over / 2;
+            let rounded = if scaled < 0 {
+                (scaled - half) / over
+            } else {
+                (scaled + half) / over
+            };
+            Self(rounded.clamp(i32::MIN as i128, i32::MAX as i128) as i32)
+        })
+    }
+
+    pub fn display(&self, unit: TemperatureUnit) -> impl fmt::Display {
+        Displayed {
+            thousandths: self.in_unit(unit),
+            unit,
+        }
+    }
+}
+
+impl fmt::Display for TemperatureDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_thousandths(f, self.0 as i64, TemperatureUnit::Celsius)
+    }
+}
+
+struct Displayed {
+    thousandths: i64,
+    unit: TemperatureUnit,
+}
+
+impl fmt::Display for Displayed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_thousandths(f, self.thousandths, self.unit)
+    }
+}
+
+impl Sub for MilliCelsius {
+    type Output = TemperatureDelta;
+
+    fn sub(self, other: Self) -> TemperatureDelta {
+        TemperatureDelta(self.0 - other.0)
+    }
+}
+
+impl Add<TemperatureDelta> for MilliCelsius {
+    type Output = MilliCelsius;
+
+    fn add(self, delta: TemperatureDelta) -> MilliCelsius {
+        MilliCelsius(self.0 + delta.0)
+    }
+}
+
+impl Sub<TemperatureDelta> for MilliCelsius {
+    type Output = MilliCelsius;
+
+    fn sub(self, delta: TemperatureDelta) -> MilliCelsius {
+        MilliCelsius(self.0 - delta.0)
+    }
+}
+
+impl Add for TemperatureDelta {
+    type Output = TemperatureDelta;
+
+    fn add(self, other: Self) -> TemperatureDelta {
+        TemperatureDelta(self.0 + other.0)
+    }
+}
+
+impl Sub for TemperatureDelta {
+    type Output = TemperatureDelta;
+
+    fn sub(self, other: Self) -> TemperatureDelta {
+        TemperatureDelta(self.0 - other.0)
+    }
+}
+
+impl Neg for TemperatureDelta {
+    type Output = TemperatureDelta;
+
+    fn neg(self) -> TemperatureDelta {
+        TemperatureDelta(-self.0)
+    }
+}
+
+impl From<Temperature> for MilliCelsius {
+    fn from(temperature: Temperature) -> Self {
+        Self(temperature.raw_value as i32 * 50 - 20_000)
+    }
+}
+
+impl From<&Temperature> for MilliCelsius {
+    fn from(temperature: &Temperature) -> Self {
+        (*temperature).into()
+    }
+}
+
+impl From<&CoreTemperature> for MilliCelsius {
+    fn from(temperature: &CoreTemperature) -> Self {
+        Self(temperature.raw_value as i32 * 100 - 20_000)
+    }
+}
+
+impl From<&PredictionSetPointTemperature> for MilliCelsius {
+    fn from(temperature: &PredictionSetPointTemperature) -> Self {
+        Self(temperature.raw_value as i32 * 100)
+    }
+}
+
+impl From<&HeatStartTemperature> for MilliCelsius {
+    fn from(temperature: &HeatStartTemperature) -> Self {
+        Self(temperature.raw_value as i32 * 100)
+    }
+}
+
+#[test]
+fn test_temperature_from_celsius() {
+    assert_eq!(Temperature::from_celsius(22.1), Temperature::new(842));
+    assert_eq!(Temperature::from_celsius(22.12), Temperature::new(842));
+    assert_eq!(Temperature::from_celsius(22.13), Temperature::new(843));
+    assert_eq!(Temperature::from_fahrenheit(71.78), Temperature::new(842));
+    assert_eq!(Temperature::from_celsius(-40.0), Temperature::new(0));
+    assert_eq!(
+        Temperature::from_celsius(1000.0),
+        Temperature::new(Temperature::MAX_RAW)
+    );
+    assert_eq!(Temperature::from_celsius(f32::NAN), Temperature::new(0));
+
+    assert_eq!(CoreTemperature::from_celsius(86.2).get_raw_value(), 1062);
+    assert_eq!(
+        PredictionSetPointTemperature::from_fahrenheit(135.0).get_raw_value(),
+        572
+    );
+    assert_eq!(HeatStartTemperature::from_celsius(-5.0).get_raw_value(), 0);
+}
+
+#[test]
+fn test_pack_temperatures() {
+    let temperatures = [842, 843, 843, 843, 851, 853, 853, 856].map(Temperature::new);
+    let packed = [
+        0x4a, 0x63, 0x69, 0x2c, 0x8d, 0xa5, 0x31, 0x35, 0xaa, 0x46, 0xd5, 0xc0, 0x1a,
+    ];
+
+    assert_eq!(pack_temperatures(&temperatures), packed);
+    assert_eq!(unpack_temperatures(packed), temperatures);
+}
+
+#[test]
+fn test_write_prediction_temperatures() {
+    let core = CoreTemperature::from_celsius(86.2);
+    let bytes = core.to_bytes().unwrap();
+    assert_eq!(CoreTemperature::from_bytes((&bytes, 0)).unwrap().1, core);
+
+    let set_point = PredictionSetPointTemperature::from_celsius(57.2);
+    let bytes = set_point.to_bytes().unwrap();
+    assert_eq!(
+        PredictionSetPointTemperature::from_bytes((&bytes, 0))
+            .unwrap()
+            .1,
+        set_point
+    );
+}
+
+#[test]
+fn test_milli_celsius_is_exact() {
+    let temperature = MilliCelsius::from(Temperature::new(843));
+    assert_eq!(temperature, MilliCelsius::new(22_150));
+    assert_eq!(format!("{temperature}"), "22.15 °C");
+    assert_eq!(
+        format!("{:.1}", temperature.display(TemperatureUnit::Fahrenheit)),
+        "71.9 °F"
+    );
+    assert_eq!(
+        format!("{}", temperature.display(TemperatureUnit::Fahrenheit)),
+        "71.87 °F"
+    );
+    assert_eq!(
+        format!("{:.3}", temperature.display(TemperatureUnit::Kelvin)),
+        "295.300 K"
+    );
+    assert_eq!(
+        format!("{}", MilliCelsius::from(Temperature::new(0))),
+        "-20.00 °C"
+    );
+
+    assert_eq!(
+        MilliCelsius::from(&CoreTemperature::new(1062)),
+        MilliCelsius::new(86_200)
+    );
+    assert_eq!(
+        MilliCelsius::from(&PredictionSetPointTemperature::new(572)),
+        MilliCelsius::new(57_200)
+    );
+}
+
+#[test]
+fn test_milli_celsius_arithmetic() {
+    let start = MilliCelsius::from(Temperature::new(842));
+    let end = MilliCelsius::from(Temperature::new(902));
+    let rise = end - start;
+    assert_eq!(rise, TemperatureDelta::new(3_000));
+    assert_eq!(start + rise, end);
+    assert!(start < end);
+    assert_eq!(
+        format!("{}", (-rise).display(TemperatureUnit::Fahrenheit)),
+        "-5.40 °F"
+    );
+
+    assert_eq!(
+        rise.rate(Duration::from_secs(90), Duration::from_secs(60)),
+        Some(TemperatureDelta::new(2_000))
+    );
+    assert_eq!(rise.rate(Duration::ZERO, Duration::from_secs(60)), None);
+
+    assert_eq!(
+        MilliCelsius::average([start, end, MilliCelsius::new(22_150)]),
+        Some(MilliCelsius::new(23_117))
+    );
+    assert_eq!(MilliCelsius::average([]), None);
+}
+
+#[test]
+fn test_reading_status() {
+    assert_eq!(
+        Temperature::new(0).get_reading_status(),
+        ReadingStatus::Underrange
+    );
+    assert_eq!(
+        Temperature::new(Temperature::MAX_RAW).get_reading_status(),
+        ReadingStatus::Overrange
+    );
+    assert_eq!(Temperature::new(1).valid(), Some(Temperature::new(1)));
+    assert_eq!(Temperature::from_celsius(-40.0).valid(), None);
+}
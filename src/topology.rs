@@ -0,0 +1,487 @@
+//! Builds a picture of the MeatNet mesh from the heartbeats nodes send to each other.
+//!
+//! Times are passed in as a `Duration` since any fixed point (e.g. when the gateway started), so
+//! this works the same on firmware and on a host.
+
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use core::cmp::Reverse;
+use core::fmt::Write as _;
+use core::time::Duration;
+use serde::Serialize;
+
+use crate::uart::node::request::{Direction, HeartbeatMessage};
+use crate::{DeviceSerial, MacAddress, ProductType};
+
+#[cfg(test)]
+use crate::uart::node::request::{Attributes, ConnectionDetailRecord};
+#[cfg(test)]
+use crate::{NodeSerialNumber, SerialNumber};
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node {
+    pub serial_number: DeviceSerial,
+    pub product_type: ProductType,
+    /// Only known for nodes we've had a heartbeat from, not for devices that have only been
+    /// seen as somebody's peer.
+    pub mac_address: Option<MacAddress>,
+    pub hop_count: Option<u8>,
+    pub last_seen: Duration,
+}
+
+/// A connection reported by `from` in its heartbeat. RSSI is as measured by `from`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Link {
+    pub from: DeviceSerial,
+    pub to: DeviceSerial,
+    pub rssi: i8,
+    pub last_seen: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Topology {
+    max_age: Duration,
+    gateway: Option<DeviceSerial>,
+    nodes: BTreeMap<DeviceSerial, Node>,
+    links: BTreeMap<(DeviceSerial, DeviceSerial), Link>,
+}
+
+impl Topology {
+    /// Nodes and links that haven't been reported for longer than `max_age` are dropped by
+    /// [`Topology::expire`].
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            ..Default::default()
+        }
+    }
+
+    /// Set the node we're attached to. Without this, the node whose heartbeats reach us without
+    /// any hops is used.
+    pub fn set_gateway(&mut self, gateway: Option<DeviceSerial>) {
+        self.gateway = gateway;
+    }
+
+    pub fn gateway(&self) -> Option<&DeviceSerial> {
+        self.gateway.as_ref().or_else(|| {
+            self.nodes
+                .values()
+                .filter(|node| node.hop_count == Some(0))
+                .max_by_key(|node| node.last_seen)
+                .map(|node| &node.serial_number)
+        })
+    }
+
+    pub fn ingest(&mut self, heartbeat: &HeartbeatMessage, now: Duration) {
+        let serial_number = DeviceSerial::Node(heartbeat.node_serial_number);
+
+        // Inbound heartbeats have travelled towards us, so their hop count says how far away the
+        // sender is.
+        let hop_count = match heartbeat.is_inbound {
+            Direction::Inbound => Some(heartbeat.hop_count),
+            Direction::Outbound => self
+                .nodes
+                .get(&serial_number)
+                .and_then(|node| node.hop_count),
+        };
+        self.nodes.insert(
+            serial_number,
+            Node {
+                serial_number,
+                product_type: heartbeat.product_type,
+                mac_address: Some(heartbeat.mac_address),
+                hop_count,
+                last_seen: now,
+            },
+        );
+
+        // A heartbeat lists all of the sender's current connections, so anything it doesn't
+        // mention has gone.
+        self.links.retain(|(from, _), _| *from != serial_number);
+
+        for record in &heartbeat.connection_details {
+            let Some(peer) = record.peer() else {
+                continue;
+            };
+
+            self.nodes
+                .entry(*peer)
+                .and_modify(|node| node.last_seen = node.last_seen.max(now))
+                .or_insert(Node {
+                    serial_number: *peer,
+                    product_type: record.product_type,
+                    mac_address: None,
+                    hop_count: None,
+                    last_seen: now,
+                });
+            self.links.insert(
+                (serial_number, *peer),
+                Link {
+                    from: serial_number,
+                    to: *peer,
+                    rssi: record.rssi_dbm(),
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Drop links and nodes that haven't been reported for longer than `max_age`.
+    pub fn expire(&mut self, now: Duration) {
+        let max_age = self.max_age;
+        self.links
+            .retain(|_, link| now.saturating_sub(link.last_seen) <= max_age);
+        self.nodes
+            .retain(|_, node| now.saturating_sub(node.last_seen) <= max_age);
+
+        let nodes = &self.nodes;
+        self.links
+            .retain(|(from, to), _| nodes.contains_key(from) && nodes.contains_key(to));
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = &Link> {
+        self.links.values()
+    }
+
+    pub fn node(&self, serial_number: &DeviceSerial) -> Option<&Node> {
+        self.nodes.get(serial_number)
+    }
+
+    /// The route from `serial_number` to the gateway with the fewest hops, starting with
+    /// `serial_number` and ending with the gateway. Links are treated as usable in both
+    /// directions. Between routes of the same length, the one whose weakest link is strongest is
+    /// preferred.
+    pub fn path_to_gateway(&self, serial_number: &DeviceSerial) -> Option<Vec<DeviceSerial>> {
+        let gateway = *self.gateway()?;
+        if !self.nodes.contains_key(serial_number) {
+            return None;
+        }
+
+        let mut neighbours: BTreeMap<DeviceSerial, Vec<(i8, DeviceSerial)>> = BTreeMap::new();
+        for link in self.links.values() {
+            neighbours
+                .entry(link.from)
+                .or_default()
+                .push((link.rssi, link.to));
+            neighbours
+                .entry(link.to)
+                .or_default()
+                .push((link.rssi, link.from));
+        }
+
+        // Hops from the gateway to everything it can reach, in the order they're found.
+        let mut hops = BTreeMap::from([(gateway, 0)]);
+        let mut by_hops = vec![gateway];
+        let mut queue = VecDeque::from([gateway]);
+        while let Some(current) = queue.pop_front() {
+            let next = hops[&current] + 1;
+            for (_, peer) in neighbours.get(&current).into_iter().flatten() {
+                if !hops.contains_key(peer) {
+                    hops.insert(*peer, next);
+                    by_hops.push(*peer);
+                    queue.push_back(*peer);
+                }
+            }
+        }
+
+        // Working outwards, each node's best route goes through whichever neighbour one hop
+        // closer gives the strongest weakest link. Those neighbours' routes are already known.
+        let mut routes: BTreeMap<DeviceSerial, (i8, DeviceSerial)> = BTreeMap::new();
+        for node in by_hops.iter().skip(1) {
+            let closer = hops[node] - 1;
+            let best = neighbours[node]
+                .iter()
+                .filter(|(_, peer)| hops.get(peer) == Some(&closer))
+                .map(|(rssi, peer)| {
+                    let weakest = routes
+                        .get(peer)
+                        .map_or(*rssi, |(weakest, _)| (*weakest).min(*rssi));
+                    (weakest, *peer)
+                })
+                .min_by_key(|(weakest, _)| Reverse(*weakest));
+            if let Some(best) = best {
+                routes.insert(*node, best);
+            }
+        }
+
+        let mut path = vec![*serial_number];
+        let mut current = *serial_number;
+        while current != gateway {
+            current = routes.get(&current)?.1;
+            path.push(current);
+        }
+        Some(path)
+    }
+
+    /// Everything known about the mesh, with serial numbers as text, ready to serialize e.g. as
+    /// JSON.
+    pub fn snapshot(&self, now: Duration) -> TopologySnapshot {
+        TopologySnapshot {
+            gateway: self.gateway().map(|gateway| format!("{}", gateway)),
+            nodes: self
+                .nodes
+                .values()
+                .map(|node| NodeSnapshot {
+                    serial_number: format!("{}", node.serial_number),
+                    product_type: node.product_type,
+                    hop_count: node.hop_count,
+                    age_ms: age_ms(now, node.last_seen),
+                    path_to_gateway: self.path_to_gateway(&node.serial_number).map(|path| {
+                        path.iter()
+                            .map(|serial_number| format!("{}", serial_number))
+                            .collect()
+                    }),
+                })
+                .collect(),
+            links: self
+                .links
+                .values()
+                .map(|link| LinkSnapshot {
+                    from: format!("{}", link.from),
+                    to: format!("{}", link.to),
+                    rssi: link.rssi,
+                    age_ms: age_ms(now, link.last_seen),
+                })
+                .collect(),
+        }
+    }
+
+    /// Render the mesh as a Graphviz digraph, with an edge per reported link.
+    pub fn to_dot(&self, now: Duration) -> String {
+        let gateway = self.gateway();
+        let mut dot = String::from("digraph meatnet {\n");
+
+        for node in self.nodes.values() {
+            let shape = match node.product_type {
+                ProductType::PredictiveProbe | ProductType::Gauge => "ellipse",
+                _ => "box",
+            };
+            let peripheries = if Some(&node.serial_number) == gateway {
+                2
+            } else {
+                1
+            };
+            let _ = writeln!(
+                dot,
+                "  \"{}\" [label=\"{}\\n{:?}\", shape={}, peripheries={}];",
+                node.serial_number, node.serial_number, node.product_type, shape, peripheries
+            );
+        }
+        for link in self.links.values() {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{} dBm, {}s\"];",
+                link.from,
+                link.to,
+                link.rssi,
+                now.saturating_sub(link.last_seen).as_secs()
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn age_ms(now: Duration, last_seen: Duration) -> u64 {
+    now.saturating_sub(last_seen).as_millis() as u64
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct NodeSnapshot {
+    pub serial_number: String,
+    pub product_type: ProductType,
+    pub hop_count: Option<u8>,
+    pub age_ms: u64,
+    pub path_to_gateway: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LinkSnapshot {
+    pub from: String,
+    pub to: String,
+    pub rssi: i8,
+    pub age_ms: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TopologySnapshot {
+    pub gateway: Option<String>,
+    pub nodes: Vec<NodeSnapshot>,
+    pub links: Vec<LinkSnapshot>,
+}
+
+#[cfg(test)]
+fn test_heartbeat(
+    serial_number: &str,
+    hop_count: u8,
+    peers: &[(DeviceSerial, ProductType, u8)],
+) -> HeartbeatMessage {
+    let empty = || ConnectionDetailRecord {
+        serial_number: DeviceSerial::Node(NodeSerialNumber::new([0; 10])),
+        product_type: ProductType::Unknown,
+        attributes: Attributes {
+            connection_detail_record_is_populated: false,
+        },
+        rssi: 0,
+    };
+    let mut connection_details = [empty(), empty(), empty(), empty()];
+    for (record, (serial_number, product_type, rssi)) in connection_details.iter_mut().zip(peers) {
+        *record = ConnectionDetailRecord {
+            serial_number: *serial_number,
+            product_type: *product_type,
+            attributes: Attributes {
+                connection_detail_record_is_populated: true,
+            },
+            rssi: *rssi,
+        };
+    }
+
+    HeartbeatMessage {
+        node_serial_number: serial_number.parse().unwrap(),
+        mac_address: MacAddress { address: [0; 6] },
+        product_type: ProductType::MeatNetRepeater,
+        hop_count,
+        is_inbound: Direction::Inbound,
+        connection_details,
+    }
+}
+
+#[test]
+fn test_path_to_gateway() {
+    let gateway = DeviceSerial::Node("GATEWAY".parse().unwrap());
+    let near = DeviceSerial::Node("NEAR".parse().unwrap());
+    let far = DeviceSerial::Node("FAR".parse().unwrap());
+    let probe = DeviceSerial::Probe(SerialNumber { number: 0x10001ded });
+
+    let mut topology = Topology::new(Duration::from_secs(30));
+    topology.ingest(
+        &test_heartbeat("GATEWAY", 0, &[(near, ProductType::MeatNetRepeater, 0xc7)]),
+        Duration::from_secs(1),
+    );
+    topology.ingest(
+        &test_heartbeat(
+            "NEAR",
+            1,
+            &[
+                (gateway, ProductType::MeatNetRepeater, 0xc5),
+                (far, ProductType::MeatNetRepeater, 0xb0),
+            ],
+        ),
+        Duration::from_secs(2),
+    );
+    topology.ingest(
+        &test_heartbeat(
+            "FAR",
+            2,
+            &[
+                (near, ProductType::MeatNetRepeater, 0xb2),
+                (probe, ProductType::PredictiveProbe, 0xd3),
+            ],
+        ),
+        Duration::from_secs(3),
+    );
+
+    assert_eq!(topology.gateway(), Some(&gateway));
+    assert_eq!(topology.nodes().count(), 4);
+    assert_eq!(
+        topology.path_to_gateway(&probe),
+        Some(vec![probe, far, near, gateway])
+    );
+
+    let snapshot = topology.snapshot(Duration::from_secs(4));
+    assert_eq!(
+        snapshot.links[0],
+        LinkSnapshot {
+            from: "FAR".into(),
+            to: "10001DED".into(),
+            rssi: -45,
+            age_ms: 1000,
+        }
+    );
+    assert!(topology
+        .to_dot(Duration::from_secs(4))
+        .contains("  \"FAR\" -> \"NEAR\" [label=\"-78 dBm, 1s\"];\n"));
+    assert!(serde_json::to_string(&snapshot)
+        .unwrap()
+        .contains("\"path_to_gateway\":[\"10001DED\",\"FAR\",\"NEAR\",\"GATEWAY\"]"));
+}
+
+#[test]
+fn test_path_to_gateway_prefers_strongest_route() {
+    let gateway = DeviceSerial::Node("GATEWAY".parse().unwrap());
+    let strong = DeviceSerial::Node("STRONG".parse().unwrap());
+    let weak = DeviceSerial::Node("WEAK".parse().unwrap());
+    let far = DeviceSerial::Node("FAR".parse().unwrap());
+
+    // Both routes are two hops. The one starting with the stronger link has the weakest link
+    // overall.
+    let mut topology = Topology::new(Duration::from_secs(30));
+    topology.ingest(
+        &test_heartbeat(
+            "GATEWAY",
+            0,
+            &[
+                (strong, ProductType::MeatNetRepeater, 0xd8),
+                (weak, ProductType::MeatNetRepeater, 0xb0),
+            ],
+        ),
+        Duration::from_secs(1),
+    );
+    topology.ingest(
+        &test_heartbeat("STRONG", 1, &[(far, ProductType::MeatNetRepeater, 0xa6)]),
+        Duration::from_secs(1),
+    );
+    topology.ingest(
+        &test_heartbeat("WEAK", 1, &[(far, ProductType::MeatNetRepeater, 0xd8)]),
+        Duration::from_secs(1),
+    );
+    topology.ingest(&test_heartbeat("FAR", 2, &[]), Duration::from_secs(1));
+
+    assert_eq!(
+        topology.path_to_gateway(&far),
+        Some(vec![far, weak, gateway])
+    );
+}
+
+#[test]
+fn test_expire_stale_links() {
+    let near = DeviceSerial::Node("NEAR".parse().unwrap());
+    let far = DeviceSerial::Node("FAR".parse().unwrap());
+
+    let mut topology = Topology::new(Duration::from_secs(30));
+    topology.ingest(
+        &test_heartbeat("GATEWAY", 0, &[(near, ProductType::MeatNetRepeater, 0xc7)]),
+        Duration::from_secs(0),
+    );
+    topology.ingest(
+        &test_heartbeat("FAR", 1, &[(near, ProductType::MeatNetRepeater, 0xb0)]),
+        Duration::from_secs(20),
+    );
+
+    topology.expire(Duration::from_secs(40));
+
+    assert_eq!(topology.gateway(), None);
+    assert_eq!(
+        topology
+            .nodes()
+            .map(|node| node.serial_number)
+            .collect::<Vec<_>>(),
+        vec![far, near]
+    );
+    assert_eq!(topology.links().count(), 1);
+    assert_eq!(topology.path_to_gateway(&far), None);
+}
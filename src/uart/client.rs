@@ -0,0 +1,593 @@
+//! Request and response over a [`Transport`], so callers can send a request and wait for its
+//! response without handling frames themselves.
+//!
+//! Node responses are matched to their request by request ID, so several requests can be in
+//! flight at once. Responses to one that arrive while waiting on another are held until they're
+//! asked for. Anything else the node sends meanwhile, such as probe status messages, is kept for
+//! [`NodeClient::poll_unsolicited`].
+//! Probe responses don't carry an ID, so they're matched by message type.
+
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use core::fmt;
+use deku::prelude::*;
+
+use crate::uart::node::{self, try_request_or_response_from, MessageType};
+use crate::uart::probe;
+use crate::uart::transport::{AsyncTransport, Transport};
+
+#[cfg(test)]
+use crate::sim::{node::test_node, probe::test_probe};
+#[cfg(test)]
+use crate::uart::transport::{block_on, LoopbackError, MockTransport};
+#[cfg(test)]
+use crate::SerialNumber;
+#[cfg(test)]
+use core::time::Duration;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ClientError<E> {
+    Transport(E),
+    /// The request couldn't be encoded.
+    Encode(DekuError),
+    /// The node answered, but couldn't do what was asked, e.g. because it doesn't know the probe.
+    Unsuccessful,
+    /// The node answered with the wrong kind of response.
+    UnexpectedResponse,
+}
+
+impl<E: fmt::Display> fmt::Display for ClientError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(error) => write!(f, "transport error: {}", error),
+            ClientError::Encode(error) => write!(f, "couldn't encode request: {}", error),
+            ClientError::Unsuccessful => write!(f, "the request was unsuccessful"),
+            ClientError::UnexpectedResponse => write!(f, "unexpected response"),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", feature = "async"))]
+impl<E: std::error::Error + 'static> std::error::Error for ClientError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Transport(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+fn encode_node_request(
+    message: node::request::RequestMessage,
+) -> Result<(u32, Vec<u8>), DekuError> {
+    let request = node::request::Request::new(message);
+    Ok((request.request_header.request_id, request.to_bytes()?))
+}
+
+/// Requests whose responses are held before the oldest is forgotten.
+const TRACKED_REQUESTS: usize = 16;
+/// Responses held for each request before the oldest are dropped.
+const RESPONSE_CAPACITY: usize = 256;
+/// Unsolicited messages held before the oldest are dropped.
+const UNSOLICITED_CAPACITY: usize = 64;
+
+/// What's been received from a node that hasn't been asked for yet.
+#[derive(Debug, Default)]
+struct NodeInbox {
+    unsolicited: VecDeque<node::request::Request>,
+    /// Responses to each request that's been sent and not forgotten.
+    responses: BTreeMap<u32, VecDeque<node::response::Response>>,
+    /// The requests in `responses`, oldest first.
+    sent: VecDeque<u32>,
+}
+
+impl NodeInbox {
+    fn sent(&mut self, request_id: u32) {
+        if self.responses.insert(request_id, VecDeque::new()).is_none() {
+            self.sent.push_back(request_id);
+        }
+        while self.sent.len() > TRACKED_REQUESTS {
+            if let Some(oldest) = self.sent.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+    }
+
+    fn forget(&mut self, request_id: u32) {
+        if self.responses.remove(&request_id).is_some() {
+            self.sent.retain(|sent| *sent != request_id);
+        }
+    }
+
+    fn take(&mut self, request_id: u32) -> Option<node::response::Response> {
+        self.responses.get_mut(&request_id)?.pop_front()
+    }
+
+    /// The response if `frame` answers `request_id`. Responses to other requests we're
+    /// expecting answers to and requests from the node are kept, and anything else is dropped.
+    fn handle_frame(&mut self, frame: &[u8], request_id: u32) -> Option<node::response::Response> {
+        match try_request_or_response_from(frame) {
+            Ok(MessageType::Response(response)) if response.header.request_id == request_id => {
+                Some(response)
+            }
+            Ok(MessageType::Response(response)) => {
+                if let Some(responses) = self.responses.get_mut(&response.header.request_id) {
+                    push_bounded(responses, response, RESPONSE_CAPACITY);
+                }
+                None
+            }
+            Ok(MessageType::Request(request)) => {
+                push_bounded(&mut self.unsolicited, request, UNSOLICITED_CAPACITY);
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, capacity: usize) {
+    if queue.len() == capacity {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+fn encode_probe_request(message: probe::request::RequestType) -> Result<(u8, Vec<u8>), DekuError> {
+    let request = probe::request::Request::new(message);
+    Ok((request.request_type, request.to_bytes()?))
+}
+
+fn match_probe_frame(frame: &[u8], response_type: u8) -> Option<probe::response::Response> {
+    probe::response::Response::try_from(frame)
+        .ok()
+        .filter(|response| response.response_type == response_type)
+}
+
+pub struct NodeClient<T> {
+    transport: T,
+    inbox: NodeInbox,
+}
+
+impl<T: Transport> NodeClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            inbox: NodeInbox::default(),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Send a request without waiting, returning its ID to pass to
+    /// [`NodeClient::receive_response`]. Responses that arrive while waiting for something else
+    /// are held until they're received, so call [`NodeClient::forget`] once no more are wanted.
+    /// Otherwise they're only dropped once enough newer requests have been sent, or too many
+    /// have piled up.
+    pub fn send(
+        &mut self,
+        message: node::request::RequestMessage,
+    ) -> Result<u32, ClientError<T::Error>> {
+        let (request_id, bytes) = encode_node_request(message).map_err(ClientError::Encode)?;
+        self.transport
+            .send_frame(&bytes)
+            .map_err(ClientError::Transport)?;
+        self.inbox.sent(request_id);
+        Ok(request_id)
+    }
+
+    /// Wait for the next response to the request with ID `request_id`. Some requests, like
+    /// reading logs, get several.
+    pub fn receive_response(
+        &mut self,
+        request_id: u32,
+    ) -> Result<node::response::Response, ClientError<T::Error>> {
+        if let Some(response) = self.inbox.take(request_id) {
+            return Ok(response);
+        }
+        loop {
+            let frame = self
+                .transport
+                .receive_frame()
+                .map_err(ClientError::Transport)?;
+            if let Some(response) = self.inbox.handle_frame(&frame, request_id) {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Send a request and wait for its response. Any further responses to it are dropped.
+    pub fn request(
+        &mut self,
+        message: node::request::RequestMessage,
+    ) -> Result<node::response::Response, ClientError<T::Error>> {
+        let request_id = self.send(message)?;
+        let response = self.receive_response(request_id);
+        self.forget(request_id);
+        response
+    }
+
+    /// Stop holding responses to the request with ID `request_id`, once no more are wanted.
+    pub fn forget(&mut self, request_id: u32) {
+        self.inbox.forget(request_id);
+    }
+
+    /// Messages the node sent by itself while we were waiting for responses.
+    pub fn poll_unsolicited(&mut self) -> Option<node::request::Request> {
+        self.inbox.unsolicited.pop_front()
+    }
+}
+
+pub struct AsyncNodeClient<T> {
+    transport: T,
+    inbox: NodeInbox,
+}
+
+impl<T: AsyncTransport> AsyncNodeClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            inbox: NodeInbox::default(),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Like [`NodeClient::send`], call [`AsyncNodeClient::forget`] once no more responses are
+    /// wanted.
+    pub async fn send(
+        &mut self,
+        message: node::request::RequestMessage,
+    ) -> Result<u32, ClientError<T::Error>> {
+        let (request_id, bytes) = encode_node_request(message).map_err(ClientError::Encode)?;
+        self.transport
+            .send_frame(&bytes)
+            .await
+            .map_err(ClientError::Transport)?;
+        self.inbox.sent(request_id);
+        Ok(request_id)
+    }
+
+    pub async fn receive_response(
+        &mut self,
+        request_id: u32,
+    ) -> Result<node::response::Response, ClientError<T::Error>> {
+        if let Some(response) = self.inbox.take(request_id) {
+            return Ok(response);
+        }
+        loop {
+            let frame = self
+                .transport
+                .receive_frame()
+                .await
+                .map_err(ClientError::Transport)?;
+            if let Some(response) = self.inbox.handle_frame(&frame, request_id) {
+                return Ok(response);
+            }
+        }
+    }
+
+    pub async fn request(
+        &mut self,
+        message: node::request::RequestMessage,
+    ) -> Result<node::response::Response, ClientError<T::Error>> {
+        let request_id = self.send(message).await?;
+        let response = self.receive_response(request_id).await;
+        self.forget(request_id);
+        response
+    }
+
+    pub fn forget(&mut self, request_id: u32) {
+        self.inbox.forget(request_id);
+    }
+
+    pub fn poll_unsolicited(&mut self) -> Option<node::request::Request> {
+        self.inbox.unsolicited.pop_front()
+    }
+}
+
+pub struct ProbeClient<T> {
+    transport: T,
+}
+
+impl<T: Transport> ProbeClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Send a request without waiting, returning the response type to pass to
+    /// [`ProbeClient::receive_response`].
+    pub fn send(
+        &mut self,
+        message: probe::request::RequestType,
+    ) -> Result<u8, ClientError<T::Error>> {
+        let (response_type, bytes) = encode_probe_request(message).map_err(ClientError::Encode)?;
+        self.transport
+            .send_frame(&bytes)
+            .map_err(ClientError::Transport)?;
+        Ok(response_type)
+    }
+
+    /// Wait for the next response of type `response_type`, dropping anything else.
+    pub fn receive_response(
+        &mut self,
+        response_type: u8,
+    ) -> Result<probe::response::Response, ClientError<T::Error>> {
+        loop {
+            let frame = self
+                .transport
+                .receive_frame()
+                .map_err(ClientError::Transport)?;
+            if let Some(response) = match_probe_frame(&frame, response_type) {
+                return Ok(response);
+            }
+        }
+    }
+
+    pub fn request(
+        &mut self,
+        message: probe::request::RequestType,
+    ) -> Result<probe::response::Response, ClientError<T::Error>> {
+        let response_type = self.send(message)?;
+        self.receive_response(response_type)
+    }
+}
+
+pub struct AsyncProbeClient<T> {
+    transport: T,
+}
+
+impl<T: AsyncTransport> AsyncProbeClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    pub async fn send(
+        &mut self,
+        message: probe::request::RequestType,
+    ) -> Result<u8, ClientError<T::Error>> {
+        let (response_type, bytes) = encode_probe_request(message).map_err(ClientError::Encode)?;
+        self.transport
+            .send_frame(&bytes)
+            .await
+            .map_err(ClientError::Transport)?;
+        Ok(response_type)
+    }
+
+    pub async fn receive_response(
+        &mut self,
+        response_type: u8,
+    ) -> Result<probe::response::Response, ClientError<T::Error>> {
+        loop {
+            let frame = self
+                .transport
+                .receive_frame()
+                .await
+                .map_err(ClientError::Transport)?;
+            if let Some(response) = match_probe_frame(&frame, response_type) {
+                return Ok(response);
+            }
+        }
+    }
+
+    pub async fn request(
+        &mut self,
+        message: probe::request::RequestType,
+    ) -> Result<probe::response::Response, ClientError<T::Error>> {
+        let response_type = self.send(message).await?;
+        self.receive_response(response_type).await
+    }
+}
+
+#[test]
+fn test_node_client_read_logs() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut node = test_node();
+    node.handle_timeout(Duration::from_secs(20));
+
+    // Status messages and heartbeats are already waiting ahead of the responses.
+    let mut client = NodeClient::new(MockTransport::new(move |frame: &[u8]| {
+        node.handle_input(frame);
+        core::iter::from_fn(|| node.poll_transmit()).collect()
+    }));
+
+    let request_id = client
+        .send(node::request::RequestMessage::ReadLogs(
+            node::request::ReadLogs {
+                probe_serial_number: serial_number,
+                sequence_number_start: 2,
+                sequence_number_end: 3,
+            },
+        ))
+        .unwrap();
+    let mut sequence_numbers = Vec::new();
+    for _ in 0..2 {
+        let response = client.receive_response(request_id).unwrap();
+        assert!(response.header.success);
+        match response.message {
+            node::response::ResponseMessage::ReadLogs(read_logs) => {
+                sequence_numbers.push(read_logs.sequence_number)
+            }
+            message => panic!("Unexpected response {message:?}"),
+        }
+    }
+    assert_eq!(sequence_numbers, [2, 3]);
+    assert_eq!(
+        client.receive_response(request_id),
+        Err(ClientError::Transport(LoopbackError::Empty))
+    );
+
+    assert!(matches!(
+        client.poll_unsolicited().map(|request| request.message),
+        Some(node::request::RequestMessage::ProbeStatusMessage(_))
+    ));
+}
+
+#[test]
+fn test_node_client_with_requests_in_flight() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let mut node = test_node();
+    node.handle_timeout(Duration::from_secs(20));
+    let mut client = NodeClient::new(MockTransport::new(move |frame: &[u8]| {
+        node.handle_input(frame);
+        core::iter::from_fn(|| node.poll_transmit()).collect()
+    }));
+
+    let read_logs = client
+        .send(node::request::RequestMessage::ReadLogs(
+            node::request::ReadLogs {
+                probe_serial_number: serial_number,
+                sequence_number_start: 2,
+                sequence_number_end: 3,
+            },
+        ))
+        .unwrap();
+    let session_information = client
+        .send(node::request::RequestMessage::ReadSessionInformation(
+            node::request::ReadSessionInformation { serial_number },
+        ))
+        .unwrap();
+
+    // The log responses arrive first, and are held while waiting for the session information.
+    assert!(matches!(
+        client
+            .receive_response(session_information)
+            .unwrap()
+            .message,
+        node::response::ResponseMessage::ReadSessionInformation(_)
+    ));
+    for sequence_number in [2, 3] {
+        match client.receive_response(read_logs).unwrap().message {
+            node::response::ResponseMessage::ReadLogs(read_logs) => {
+                assert_eq!(read_logs.sequence_number, sequence_number)
+            }
+            message => panic!("Unexpected response {message:?}"),
+        }
+    }
+
+    // Once forgotten, responses aren't held any more.
+    let read_logs = client
+        .send(node::request::RequestMessage::ReadLogs(
+            node::request::ReadLogs {
+                probe_serial_number: serial_number,
+                sequence_number_start: 2,
+                sequence_number_end: 3,
+            },
+        ))
+        .unwrap();
+    client.forget(read_logs);
+    assert_eq!(
+        client.receive_response(session_information),
+        Err(ClientError::Transport(LoopbackError::Empty))
+    );
+    assert!(client.inbox.responses.values().all(VecDeque::is_empty));
+}
+
+#[test]
+fn test_node_inbox_is_bounded() {
+    let mut inbox = NodeInbox::default();
+    let response = |request_id| {
+        node::response::Response::new_with_id(
+            node::response::ResponseMessage::ReadSessionInformation(
+                node::response::ReadSessionInformation {
+                    probe_serial_number: SerialNumber { number: 0x10001ded },
+                    probe_session_id: 0x22f5febc,
+                    probe_sample_period: 5000,
+                },
+            ),
+            request_id,
+            0,
+            true,
+        )
+        .to_bytes()
+        .unwrap()
+    };
+
+    // Requests that are never forgotten stop being tracked once there are enough newer ones.
+    for request_id in 0..TRACKED_REQUESTS as u32 + 1 {
+        inbox.sent(request_id);
+    }
+    inbox.handle_frame(&response(0), u32::MAX);
+    assert_eq!(inbox.take(0), None);
+
+    // A request that keeps getting responses only holds the newest.
+    for _ in 0..RESPONSE_CAPACITY + 1 {
+        inbox.handle_frame(&response(1), u32::MAX);
+    }
+    assert_eq!(inbox.responses[&1].len(), RESPONSE_CAPACITY);
+
+    let request =
+        node::request::Request::new(node::request::RequestMessage::ReadSessionInformation(
+            node::request::ReadSessionInformation {
+                serial_number: SerialNumber { number: 0x10001ded },
+            },
+        ))
+        .to_bytes()
+        .unwrap();
+    for _ in 0..UNSOLICITED_CAPACITY + 1 {
+        inbox.handle_frame(&request, u32::MAX);
+    }
+    assert_eq!(inbox.unsolicited.len(), UNSOLICITED_CAPACITY);
+}
+
+#[test]
+fn test_async_probe_client() {
+    let mut probe = test_probe();
+    probe.handle_timeout(Duration::from_secs(20));
+    let mut client = AsyncProbeClient::new(MockTransport::new(move |frame: &[u8]| {
+        probe.handle_uart_bytes(frame).unwrap()
+    }));
+
+    let response = block_on(
+        client.request(probe::request::RequestType::ReadSessionInformation(
+            probe::request::ReadSessionInformation {},
+        )),
+    )
+    .unwrap();
+    assert_eq!(
+        response,
+        probe::response::Response::new(
+            probe::response::ResponseMessage::ReadSessionInformation(
+                probe::response::ReadSessionInformation {
+                    probe_session_id: 0x22f5febc,
+                    probe_sample_period: 5000,
+                }
+            ),
+            true
+        )
+    );
+    assert_eq!(client.transport().sent().len(), 1);
+}
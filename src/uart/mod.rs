@@ -1,2 +1,8 @@
+pub mod client;
 pub mod node;
 pub mod probe;
+#[cfg(feature = "std")]
+pub mod serial;
+#[cfg(feature = "async")]
+pub mod tokio_client;
+pub mod transport;
@@ -0,0 +1,99 @@
+//! Splitting a node UART byte stream back into frames.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use crc::{Crc, CRC_16_IBM_3740};
+
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+pub const MAGIC: [u8; 2] = [0xca, 0xfe];
+pub const REQUEST_HEADER_LENGTH: usize = 10;
+pub const RESPONSE_HEADER_LENGTH: usize = 15;
+
+/// The length of the frame at the start of `bytes`, or `None` until enough of the header has
+/// arrived to tell.
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    let message_type = *bytes.get(4)?;
+    // Responses have the top bit of the message type set.
+    let header_length = if message_type >> 7 == 0 {
+        REQUEST_HEADER_LENGTH
+    } else {
+        RESPONSE_HEADER_LENGTH
+    };
+    let payload_length = *bytes.get(header_length - 1)? as usize;
+    Some(header_length + payload_length)
+}
+
+/// Whether the CRC in a complete frame matches the rest of it.
+pub fn crc_is_valid(frame: &[u8]) -> bool {
+    if frame.len() < 4 {
+        return false;
+    }
+    let crc = u16::from_le_bytes([frame[2], frame[3]]);
+    Crc::<u16>::new(&CRC_16_IBM_3740).checksum(&frame[4..]) == crc
+}
+
+/// Buffers bytes as they arrive and hands back each complete frame with a valid CRC. Anything
+/// else, such as line noise or the rest of a frame we started receiving part way through, is
+/// skipped.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let Some(start) = self.buffer.windows(2).position(|window| window == MAGIC) else {
+                // Keep a trailing first byte of the magic in case the second is still to come.
+                let keep = usize::from(self.buffer.last() == Some(&MAGIC[0]));
+                self.buffer.drain(..self.buffer.len() - keep);
+                return None;
+            };
+            self.buffer.drain(..start);
+
+            let length = frame_length(&self.buffer)?;
+            if self.buffer.len() < length {
+                return None;
+            }
+            if crc_is_valid(&self.buffer[..length]) {
+                return Some(self.buffer.drain(..length).collect());
+            }
+            // Not really the start of a frame, so look for the next one.
+            self.buffer.drain(..1);
+        }
+    }
+}
+
+#[test]
+fn test_frame_decoder_resyncs() {
+    let frame = vec![
+        0xca, 0xfe, 0xe9, 0xb5, 0x03, 0x42, 0xcd, 0x50, 0xa8, 0x04, 0xed, 0x1d, 0x00, 0x10,
+    ];
+    let mut corrupted = frame.clone();
+    corrupted[10] = 0;
+
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&[0x00, 0xca, 0xfe, 0xca]);
+    decoder.push(&corrupted);
+    decoder.push(&frame[..7]);
+    assert_eq!(decoder.next_frame(), None);
+
+    decoder.push(&frame[7..]);
+    decoder.push(&frame);
+    assert_eq!(decoder.next_frame(), Some(frame.clone()));
+    assert_eq!(decoder.next_frame(), Some(frame));
+    assert_eq!(decoder.next_frame(), None);
+}
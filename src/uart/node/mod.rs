@@ -1,11 +1,14 @@
+pub mod framing;
 pub mod request;
 pub mod response;
+pub mod segmentation;
 
 use deku::prelude::*;
 
 use request::Request;
 use response::Response;
 
+#[derive(Debug, PartialEq)]
 pub enum MessageType {
     Request(Request),
     Response(Response),
@@ -1,21 +1,32 @@
 extern crate alloc;
 
-use alloc::string::ToString;
 #[cfg(test)]
 use alloc::vec;
-use alloc::{borrow::Cow, format, vec::Vec};
+use alloc::{format, vec::Vec};
+use core::fmt;
 use crc::{Crc, CRC_16_IBM_3740};
-use deku::no_std_io::{Seek, Write};
+use deku::no_std_io::{Read, Seek, Write};
 use deku::prelude::*;
 use rand::rngs::SmallRng;
 use rand::{RngCore as _, SeedableRng};
 
-use crate::{MacAddress, NetworkInformation, ProbeStatus, ProductType, SerialNumber};
+use crate::gauge::{
+    read_gauge_temperature, write_gauge_temperature, GaugeStatusFlags, HighLowAlarmStatus,
+};
+use crate::temperature::Temperature;
+use crate::{
+    Color, DeviceSerial, MacAddress, NetworkInformation, NodeSerialNumber, ProbeStatus,
+    ProductType, SerialNumber,
+};
 
 use crate::EncapsulatableMessage;
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
-pub struct SetProbeId {}
+pub struct SetProbeId {
+    pub probe_serial_number: SerialNumber,
+    #[deku(bits = "3", pad_bits_before = "5")]
+    pub probe_id: u8,
+}
 
 impl EncapsulatableMessage for SetProbeId {
     type Encapsulation = Request;
@@ -25,7 +36,11 @@ impl EncapsulatableMessage for SetProbeId {
 }
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
-pub struct SetProbeColor {}
+pub struct SetProbeColor {
+    pub probe_serial_number: SerialNumber,
+    #[deku(pad_bits_before = "5")]
+    pub color: Color,
+}
 
 impl EncapsulatableMessage for SetProbeColor {
     type Encapsulation = Request;
@@ -61,31 +76,39 @@ impl EncapsulatableMessage for ReadLogs {
 }
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+pub struct ReadGaugeLogs {
+    pub gauge_serial_number: NodeSerialNumber,
+    pub sequence_number_start: u32,
+    pub sequence_number_end: u32,
+}
+
+impl EncapsulatableMessage for ReadGaugeLogs {
+    type Encapsulation = Request;
+    fn encapsulate(self) -> Request {
+        Request::new(RequestMessage::ReadGaugeLogs(self))
+    }
+}
+
+#[derive(Debug, PartialEq, DekuWrite, DekuRead, Clone)]
 #[deku(id_type = "u8")]
 pub enum Direction {
     Outbound = 0,
     Inbound,
 }
 
-#[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+#[derive(Debug, PartialEq, DekuWrite, DekuRead, Clone)]
 pub struct Attributes {
     #[deku(bits = "1", pad_bits_before = "7")]
     pub connection_detail_record_is_populated: bool,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone)]
 pub struct ProbeStatusMessage {
     pub probe_serial_number: SerialNumber,
     pub status: ProbeStatus,
     pub network_information: NetworkInformation,
 }
 
-impl DekuWriter for ProbeStatusMessage {
-    fn to_writer<W: Write + Seek>(&self, _: &mut Writer<W>, _: ()) -> Result<(), DekuError> {
-        Err(DekuError::Parse(Cow::from("Not implimented".to_string())))
-    }
-}
-
 impl EncapsulatableMessage for ProbeStatusMessage {
     type Encapsulation = Request;
     fn encapsulate(self) -> Request {
@@ -93,17 +116,59 @@ impl EncapsulatableMessage for ProbeStatusMessage {
     }
 }
 
-#[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ConnectionDetailRecord {
-    pub serial_number: [u8; 10],
+    pub serial_number: DeviceSerial,
     pub product_type: ProductType,
     pub attributes: Attributes,
     pub rssi: u8,
 }
 
-#[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+impl ConnectionDetailRecord {
+    /// The serial number of the connected device, if this record is populated.
+    pub fn peer(&self) -> Option<&DeviceSerial> {
+        self.attributes
+            .connection_detail_record_is_populated
+            .then_some(&self.serial_number)
+    }
+
+    /// The RSSI is sent as a signed dBm value.
+    pub fn rssi_dbm(&self) -> i8 {
+        self.rssi as i8
+    }
+}
+
+// The serial number is sent before the product type that says how to interpret it, so this can't
+// be derived.
+impl<'a> DekuReader<'a> for ConnectionDetailRecord {
+    fn from_reader_with_ctx<R: Read + Seek>(
+        reader: &mut Reader<R>,
+        _: (),
+    ) -> Result<Self, DekuError> {
+        let raw_serial_number = <[u8; 10]>::from_reader_with_ctx(reader, ())?;
+        let product_type = ProductType::from_reader_with_ctx(reader, ())?;
+
+        Ok(Self {
+            serial_number: DeviceSerial::from_raw(raw_serial_number, &product_type),
+            product_type,
+            attributes: Attributes::from_reader_with_ctx(reader, ())?,
+            rssi: u8::from_reader_with_ctx(reader, ())?,
+        })
+    }
+}
+
+impl DekuWriter for ConnectionDetailRecord {
+    fn to_writer<W: Write + Seek>(&self, writer: &mut Writer<W>, _: ()) -> Result<(), DekuError> {
+        self.serial_number.to_raw().to_writer(writer, ())?;
+        self.product_type.to_writer(writer, ())?;
+        self.attributes.to_writer(writer, ())?;
+        self.rssi.to_writer(writer, ())
+    }
+}
+
+#[derive(Debug, PartialEq, DekuWrite, DekuRead, Clone)]
 pub struct HeartbeatMessage {
-    pub node_serial_number: [u8; 10],
+    pub node_serial_number: NodeSerialNumber,
     pub mac_address: MacAddress,
     pub product_type: ProductType,
     pub hop_count: u8,
@@ -119,18 +184,130 @@ impl EncapsulatableMessage for HeartbeatMessage {
 }
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
+pub struct GaugeStatusMessage {
+    pub gauge_serial_number: NodeSerialNumber,
+    pub session_id: u32,
+    pub sample_period: u16,
+    #[deku(
+        reader = "read_gauge_temperature(deku::reader)",
+        writer = "write_gauge_temperature(deku::writer, &self.temperature)"
+    )]
+    pub temperature: Temperature,
+    pub status_flags: GaugeStatusFlags,
+    pub min_sequence_number: u32,
+    pub max_sequence_number: u32,
+    pub high_low_alarm_status: HighLowAlarmStatus,
+    pub network_information: NetworkInformation,
+}
+
+impl EncapsulatableMessage for GaugeStatusMessage {
+    type Encapsulation = Request;
+    fn encapsulate(self) -> Request {
+        Request::new(RequestMessage::GaugeStatusMessage(self))
+    }
+}
+
+#[derive(Debug, PartialEq, DekuWrite, DekuRead, Clone, Copy)]
 pub struct SyncThermometer {
     #[deku(bytes = "1")]
     present: bool,
     serial_number: SerialNumber,
 }
 
+impl SyncThermometer {
+    pub fn new(serial_number: SerialNumber) -> Self {
+        Self {
+            present: true,
+            serial_number,
+        }
+    }
+
+    /// An unused slot in the list.
+    pub fn empty() -> Self {
+        Self {
+            present: false,
+            serial_number: SerialNumber { number: 0 },
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    pub fn serial_number(&self) -> Option<SerialNumber> {
+        self.present.then_some(self.serial_number)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyncThermometerListError {
+    TooManyThermometers(usize),
+}
+
+impl fmt::Display for SyncThermometerListError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncThermometerListError::TooManyThermometers(count) => write!(
+                f,
+                "{} thermometers given, at most {} can be synced",
+                count,
+                SyncThermometerList::MAX_THERMOMETERS
+            ),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
 pub struct SyncThermometerList {
     mac_address: MacAddress,
     sync_thermometers: [SyncThermometer; 4],
 }
 
+impl SyncThermometerList {
+    pub const MAX_THERMOMETERS: usize = 4;
+
+    pub fn new(
+        mac_address: MacAddress,
+        serial_numbers: &[SerialNumber],
+    ) -> Result<Self, SyncThermometerListError> {
+        if serial_numbers.len() > Self::MAX_THERMOMETERS {
+            return Err(SyncThermometerListError::TooManyThermometers(
+                serial_numbers.len(),
+            ));
+        }
+
+        let mut sync_thermometers = [SyncThermometer::empty(); 4];
+        for (slot, serial_number) in sync_thermometers.iter_mut().zip(serial_numbers) {
+            *slot = SyncThermometer::new(*serial_number);
+        }
+
+        Ok(Self {
+            mac_address,
+            sync_thermometers,
+        })
+    }
+
+    pub fn mac_address(&self) -> &MacAddress {
+        &self.mac_address
+    }
+
+    /// All four slots, including the ones that aren't in use.
+    pub fn sync_thermometers(&self) -> &[SyncThermometer; 4] {
+        &self.sync_thermometers
+    }
+
+    /// Serial numbers of the thermometers that are present in the list.
+    pub fn iter(&self) -> impl Iterator<Item = SerialNumber> + '_ {
+        self.sync_thermometers
+            .iter()
+            .filter_map(SyncThermometer::serial_number)
+    }
+
+    pub fn contains(&self, serial_number: &SerialNumber) -> bool {
+        self.iter().any(|present| present == *serial_number)
+    }
+}
+
 impl EncapsulatableMessage for SyncThermometerList {
     type Encapsulation = Request;
     fn encapsulate(self) -> Request {
@@ -155,6 +332,10 @@ pub enum RequestMessage {
     HeartbeatMessage(HeartbeatMessage),
     #[deku(id = "0x4b")]
     SyncThermometerList(SyncThermometerList),
+    #[deku(id = "0x60")]
+    GaugeStatusMessage(GaugeStatusMessage),
+    #[deku(id = "0x62")]
+    ReadGaugeLogs(ReadGaugeLogs),
 }
 impl RequestMessage {
     pub fn to_bytes(&self) -> Result<Vec<u8>, DekuError> {
@@ -163,11 +344,11 @@ impl RequestMessage {
             RequestMessage::SetProbeColor(r) => r.to_bytes(),
             RequestMessage::ReadSessionInformation(r) => r.to_bytes(),
             RequestMessage::ReadLogs(r) => r.to_bytes(),
-            RequestMessage::ProbeStatusMessage(_) => {
-                Err(DekuError::Parse(Cow::from("Not implimented".to_string())))
-            }
+            RequestMessage::ProbeStatusMessage(r) => r.to_bytes(),
             RequestMessage::HeartbeatMessage(r) => r.to_bytes(),
             RequestMessage::SyncThermometerList(r) => r.to_bytes(),
+            RequestMessage::GaugeStatusMessage(r) => r.to_bytes(),
+            RequestMessage::ReadGaugeLogs(r) => r.to_bytes(),
         }
     }
 
@@ -182,6 +363,8 @@ impl RequestMessage {
             RequestMessage::ProbeStatusMessage(r) => r.encapsulate(),
             RequestMessage::HeartbeatMessage(r) => r.encapsulate(),
             RequestMessage::SyncThermometerList(r) => r.encapsulate(),
+            RequestMessage::GaugeStatusMessage(r) => r.encapsulate(),
+            RequestMessage::ReadGaugeLogs(r) => r.encapsulate(),
         }
     }
 }
@@ -209,7 +392,8 @@ impl Request {
         Request::new_with_id(message, SmallRng::from_os_rng().next_u32())
     }
 
-    fn new_with_id(message: RequestMessage, request_id: u32) -> Self {
+    /// Build a request with a given ID, e.g. to forward a message under its original ID.
+    pub fn new_with_id(message: RequestMessage, request_id: u32) -> Self {
         let binding = Crc::<u16>::new(&CRC_16_IBM_3740);
         let mut digest = binding.digest();
 
@@ -220,8 +404,6 @@ impl Request {
         let message_bytes = message.to_bytes().unwrap();
 
         // CRC of message type, request ID, payload length, and payload bytes.
-        // TODO: when implementing response messages, this will need to be updated:
-        // CRC of message type, request ID, response ID, success, payload length, and payload bytes
         digest.update(&[message_type_id]);
         digest.update(request_id.to_le_bytes().as_slice());
         digest.update(&[message_bytes.len() as u8]);
@@ -245,7 +427,7 @@ impl Request {
 #[test]
 fn test_heartbeat_message_to_bytes() {
     let heartbeat_message = RequestMessage::HeartbeatMessage(HeartbeatMessage {
-        node_serial_number: [84, 49, 48, 48, 48, 48, 48, 51, 75, 86],
+        node_serial_number: "T1000003KV".parse().unwrap(),
         mac_address: MacAddress {
             address: [0xc1, 0x88, 0x0b, 0xca, 0x6e, 0x81],
         },
@@ -254,7 +436,7 @@ fn test_heartbeat_message_to_bytes() {
         is_inbound: Direction::Inbound,
         connection_details: [
             ConnectionDetailRecord {
-                serial_number: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                serial_number: DeviceSerial::Node(NodeSerialNumber::new([0; 10])),
                 product_type: ProductType::Unknown,
                 attributes: Attributes {
                     connection_detail_record_is_populated: false,
@@ -262,7 +444,7 @@ fn test_heartbeat_message_to_bytes() {
                 rssi: 0,
             },
             ConnectionDetailRecord {
-                serial_number: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                serial_number: DeviceSerial::Node(NodeSerialNumber::new([0; 10])),
                 product_type: ProductType::MeatNetRepeater,
                 attributes: Attributes {
                     connection_detail_record_is_populated: true,
@@ -270,7 +452,9 @@ fn test_heartbeat_message_to_bytes() {
                 rssi: 199,
             },
             ConnectionDetailRecord {
-                serial_number: [57, 15, 2, 0, 0, 0, 0, 0, 0, 0],
+                serial_number: DeviceSerial::Node(NodeSerialNumber::new([
+                    57, 15, 2, 0, 0, 0, 0, 0, 0, 0,
+                ])),
                 product_type: ProductType::MeatNetRepeater,
                 attributes: Attributes {
                     connection_detail_record_is_populated: true,
@@ -278,7 +462,7 @@ fn test_heartbeat_message_to_bytes() {
                 rssi: 211,
             },
             ConnectionDetailRecord {
-                serial_number: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                serial_number: DeviceSerial::Node(NodeSerialNumber::new([0; 10])),
                 product_type: ProductType::Unknown,
                 attributes: Attributes {
                     connection_detail_record_is_populated: false,
@@ -317,3 +501,88 @@ fn test_session_information_message_to_bytes() {
 
     assert_eq!(nm.to_bytes().unwrap(), expected)
 }
+
+#[test]
+fn test_gauge_status_message_round_trip() {
+    let gauge_status = RequestMessage::GaugeStatusMessage(GaugeStatusMessage {
+        gauge_serial_number: "G00000012A".parse().unwrap(),
+        session_id: 0x22f5febc,
+        sample_period: 5000,
+        temperature: Temperature::new(874),
+        status_flags: GaugeStatusFlags {
+            low_battery: false,
+            sensor_overheating: false,
+            sensor_present: true,
+        },
+        min_sequence_number: 0,
+        max_sequence_number: 99,
+        high_low_alarm_status: HighLowAlarmStatus {
+            high: crate::gauge::AlarmStatus::from_raw(0x1b55),
+            low: crate::gauge::AlarmStatus::from_raw(0),
+        },
+        network_information: NetworkInformation {
+            hop_count: crate::Hops::Two,
+        },
+    });
+
+    let request = Request::new_with_id(gauge_status, 0xa850cd42);
+    let bytes = request.to_bytes().unwrap();
+
+    assert_eq!(
+        bytes,
+        vec![
+            0xca, 0xfe, 0xa1, 0xbf, 0x60, 0x42, 0xcd, 0x50, 0xa8, 0x20, 0x47, 0x30, 0x30, 0x30,
+            0x30, 0x30, 0x30, 0x31, 0x32, 0x41, 0xbc, 0xfe, 0xf5, 0x22, 0x88, 0x13, 0x6a, 0x03,
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x63, 0x00, 0x00, 0x00, 0x55, 0x1b, 0x00, 0x00, 0x01
+        ]
+    );
+    assert_eq!(Request::try_from(bytes.as_slice()).unwrap(), request);
+}
+
+#[test]
+fn test_sync_thermometer_list_round_trip() {
+    let mac_address = MacAddress {
+        address: [0xc1, 0x88, 0x0b, 0xca, 0x6e, 0x81],
+    };
+    let serial_numbers = [
+        SerialNumber { number: 0x10001ded },
+        SerialNumber { number: 0x10000f39 },
+    ];
+    let sync_thermometer_list = SyncThermometerList::new(mac_address, &serial_numbers).unwrap();
+
+    assert_eq!(
+        sync_thermometer_list.iter().collect::<Vec<_>>(),
+        serial_numbers
+    );
+    assert!(!sync_thermometer_list.sync_thermometers()[2].is_present());
+
+    let request = Request::new_with_id(
+        RequestMessage::SyncThermometerList(sync_thermometer_list),
+        0xa850cd42,
+    );
+    let bytes = request.to_bytes().unwrap();
+
+    assert_eq!(
+        bytes[9..],
+        [
+            0x1a, 0xc1, 0x88, 0x0b, 0xca, 0x6e, 0x81, 0x01, 0xed, 0x1d, 0x00, 0x10, 0x01, 0x39,
+            0x0f, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+        ]
+    );
+    assert_eq!(Request::try_from(bytes.as_slice()).unwrap(), request);
+}
+
+#[test]
+fn test_sync_thermometer_list_too_many_thermometers() {
+    let serial_numbers = [SerialNumber { number: 1 }; 5];
+
+    assert_eq!(
+        SyncThermometerList::new(
+            MacAddress {
+                address: [0, 0, 0, 0, 0, 0]
+            },
+            &serial_numbers
+        ),
+        Err(SyncThermometerListError::TooManyThermometers(5))
+    );
+}
@@ -3,32 +3,49 @@ extern crate alloc;
 #[cfg(test)]
 use alloc::vec;
 use alloc::{format, vec::Vec};
+use crc::{Crc, CRC_16_IBM_3740};
 use deku::prelude::*;
+use rand::rngs::SmallRng;
+use rand::{RngCore as _, SeedableRng};
 
 use crate::SerialNumber;
 
+mod readgaugelogs;
 mod readlogs;
+pub use readgaugelogs::ReadGaugeLogs;
 pub use readlogs::ReadLogs;
 
-#[derive(Debug, PartialEq, DekuRead)]
-struct SetProbeId {}
-
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct ReadSessionInformation {
     pub probe_serial_number: SerialNumber,
     pub probe_session_id: u32,
     pub probe_sample_period: u16,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(ctx = "response_type: u8", id = "response_type")]
 pub enum ResponseMessage {
     #[deku(id = "0x01")]
     SetProbeId,
+    #[deku(id = "0x02")]
+    SetProbeColor,
     #[deku(id = "0x03")]
     ReadSessionInformation(ReadSessionInformation),
     #[deku(id = "0x04")]
     ReadLogs(ReadLogs),
+    #[deku(id = "0x62")]
+    ReadGaugeLogs(ReadGaugeLogs),
+}
+
+impl ResponseMessage {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DekuError> {
+        match self {
+            ResponseMessage::SetProbeId | ResponseMessage::SetProbeColor => Ok(Vec::new()),
+            ResponseMessage::ReadSessionInformation(r) => r.to_bytes(),
+            ResponseMessage::ReadLogs(r) => r.to_bytes(),
+            ResponseMessage::ReadGaugeLogs(r) => r.to_bytes(),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
@@ -45,13 +62,61 @@ pub struct ResponseHeader {
     payload_length: u8,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct Response {
     pub header: ResponseHeader,
     #[deku(ctx = "header.response_type & 0b01111111")]
     pub message: ResponseMessage,
 }
 
+impl Response {
+    /// Build a response to the request with ID `request_id`.
+    pub fn new(message: ResponseMessage, request_id: u32, success: bool) -> Self {
+        Response::new_with_id(
+            message,
+            request_id,
+            SmallRng::from_os_rng().next_u32(),
+            success,
+        )
+    }
+
+    pub fn new_with_id(
+        message: ResponseMessage,
+        request_id: u32,
+        response_id: u32,
+        success: bool,
+    ) -> Self {
+        let binding = Crc::<u16>::new(&CRC_16_IBM_3740);
+        let mut digest = binding.digest();
+
+        let response_type = message
+            .deku_id()
+            .expect("New message doesn't have Deku id.")
+            | 0b10000000;
+        let message_bytes = message.to_bytes().unwrap();
+
+        // CRC of message type, request ID, response ID, success, payload length, and payload bytes.
+        digest.update(&[response_type]);
+        digest.update(request_id.to_le_bytes().as_slice());
+        digest.update(response_id.to_le_bytes().as_slice());
+        digest.update(&[success as u8]);
+        digest.update(&[message_bytes.len() as u8]);
+        digest.update(&message_bytes);
+
+        Self {
+            header: ResponseHeader {
+                crc: digest.finalize(),
+                response_type,
+                request_id,
+                response_id,
+                success,
+                payload_length: message_bytes.len() as u8,
+            },
+            message,
+        }
+    }
+}
+
 #[test]
 fn test_wont_parse_request() {
     let data = vec![
@@ -0,0 +1,56 @@
+extern crate alloc;
+
+#[cfg(test)]
+use alloc::vec;
+use alloc::vec::Vec;
+use deku::prelude::*;
+
+use crate::gauge::{read_gauge_temperature, write_gauge_temperature, GaugeStatusFlags};
+use crate::temperature::Temperature;
+use crate::NodeSerialNumber;
+
+#[cfg(test)]
+use crate::uart::node::response::{Response, ResponseHeader, ResponseMessage};
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone)]
+pub struct ReadGaugeLogs {
+    pub gauge_serial_number: NodeSerialNumber,
+    pub sequence_number: u32,
+    #[deku(
+        reader = "read_gauge_temperature(deku::reader)",
+        writer = "write_gauge_temperature(deku::writer, &self.temperature)"
+    )]
+    pub temperature: Temperature,
+    pub status_flags: GaugeStatusFlags,
+}
+
+#[test]
+fn test_parse_read_gauge_logs_response() {
+    let data = vec![
+        0xca, 0xfe, 0x3f, 0x0c, 0xe2, 0xb3, 0x69, 0x4c, 0x0a, 0x42, 0x4f, 0x95, 0x44, 0x01, 0x11,
+        0x47, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x31, 0x32, 0x41, 0x02, 0x00, 0x00, 0x00, 0x6a,
+        0x03, 0x01,
+    ];
+
+    let expected = Response {
+        header: ResponseHeader {
+            crc: 0x0c3f,
+            response_type: 0xe2,
+            request_id: 172779955,
+            response_id: 1150635842,
+            success: true,
+            payload_length: 17,
+        },
+        message: ResponseMessage::ReadGaugeLogs(ReadGaugeLogs {
+            gauge_serial_number: "G00000012A".parse().unwrap(),
+            sequence_number: 2,
+            temperature: Temperature::new(874),
+            status_flags: GaugeStatusFlags {
+                low_battery: false,
+                sensor_overheating: false,
+                sensor_present: true,
+            },
+        }),
+    };
+    assert_eq!(expected, Response::try_from(data.as_slice()).unwrap());
+}
@@ -1,24 +1,34 @@
 extern crate alloc;
 
-use alloc::format;
 #[cfg(test)]
 use alloc::vec;
+use alloc::vec::Vec;
 use deku::ctx::BitSize;
 use deku::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::{parse_raw_temperature_data, temperature::Temperature, SerialNumber};
+use crate::{
+    parse_raw_temperature_data, temperature::Temperature, write_raw_temperature_data, Mode,
+    ProbeReadings, SerialNumber,
+};
 
 #[cfg(test)]
 use crate::uart::node::response::{Response, ResponseHeader, ResponseMessage};
 
-#[derive(Debug, PartialEq, DekuRead, Clone, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite, Clone, Serialize, Deserialize)]
 pub struct ReadLogs {
     pub probe_serial_number: SerialNumber,
     pub sequence_number: u32,
-    #[deku(reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))")]
+    #[deku(
+        reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))",
+        writer = "write_raw_temperature_data(deku::writer, &self.temperatures)"
+    )]
     pub temperatures: [Temperature; 8],
-    #[deku(bits = "2", pad_bits_before = "1")]
+    /// The last bit of the estimated core temperature, which isn't decoded yet but is kept so
+    /// the log can be re-encoded unchanged.
+    #[deku(bits = "1")]
+    pub estimated_core_temperature_bit: u8,
+    #[deku(bits = "2")]
     pub virtual_ambient_sensor: u8,
     #[deku(bits = "2")]
     pub virtual_surface_sensor: u8,
@@ -39,6 +49,29 @@ impl ReadLogs {
     }
 }
 
+impl ProbeReadings for ReadLogs {
+    fn get_temperatures(&self) -> &[Temperature; 8] {
+        &self.temperatures
+    }
+
+    /// Log entries don't record the probe's mode, so only the raw values are checked.
+    fn get_mode(&self) -> Option<Mode> {
+        None
+    }
+
+    fn get_virtual_core_sensor(&self) -> u8 {
+        self.virtual_core_sensor
+    }
+
+    fn get_virtual_surface_sensor(&self) -> u8 {
+        self.virtual_surface_sensor
+    }
+
+    fn get_virtual_ambient_sensor(&self) -> u8 {
+        self.virtual_ambient_sensor
+    }
+}
+
 #[test]
 fn test_parse_read_logs_response() {
     let data = vec![
@@ -59,11 +92,17 @@ fn test_parse_read_logs_response() {
             Temperature::new(915),
             Temperature::new(915),
         ],
+        estimated_core_temperature_bit: 1,
         virtual_ambient_sensor: 3,
         virtual_surface_sensor: 0,
         virtual_core_sensor: 0,
         virtual_sensors_and_state: [0, 0, 254, 255, 215, 7],
     };
+    assert_eq!(
+        read_logs.get_valid_ambient_temperature(),
+        Some(Temperature::new(915))
+    );
+    assert_eq!(read_logs.get_instant_read_temperature(), None);
 
     let expected = Response {
         header: ResponseHeader {
@@ -74,9 +113,18 @@ fn test_parse_read_logs_response() {
             success: true,
             payload_length: 28,
         },
-        message: ResponseMessage::ReadLogs(read_logs),
+        message: ResponseMessage::ReadLogs(read_logs.clone()),
     };
     assert_eq!(expected, Response::try_from(data.as_slice()).unwrap());
+
+    let response = Response::new_with_id(
+        ResponseMessage::ReadLogs(read_logs),
+        172779955,
+        1150635842,
+        true,
+    );
+    assert_eq!(response.to_bytes().unwrap(), data);
+    assert_eq!(Response::try_from(data.as_slice()).unwrap(), response);
 }
 
 // Use the below (reversing the order of the fields after temperatures) when Deku supports lsb0.
@@ -0,0 +1,298 @@
+//! Splitting frames to fit BLE writes and notifications, and putting them back together.
+//!
+//! Each write or notification carries at most the ATT MTU less 3 bytes of header, only 20 bytes
+//! with the default MTU, so most node frames take several. Unlike a serial line, where
+//! [`FrameDecoder`](super::framing::FrameDecoder) has to search for the start of each frame, every
+//! frame here starts at the beginning of a notification. That's used to notice when fragments
+//! go missing rather than waiting for a CRC to fail.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use super::framing::{self, crc_is_valid, MAGIC};
+
+#[cfg(test)]
+use super::request::{ReadLogs, Request, RequestMessage};
+#[cfg(test)]
+use crate::SerialNumber;
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use deku::DekuContainerWrite;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// The ATT MTU before any exchange, leaving 20 bytes per write or notification.
+pub const DEFAULT_ATT_MTU: usize = 23;
+const ATT_HEADER_LENGTH: usize = 3;
+
+/// How many bytes of a frame fit in one write or notification.
+pub fn fragment_length(mtu: usize) -> usize {
+    mtu.saturating_sub(ATT_HEADER_LENGTH).max(1)
+}
+
+/// Split encoded frame bytes, e.g. from `Request::to_bytes`, into pieces that each fit in one
+/// write. Each frame should be segmented separately so every frame starts a new write.
+pub fn segment(frame: &[u8], mtu: usize) -> impl Iterator<Item = &[u8]> {
+    frame.chunks(fragment_length(mtu))
+}
+
+/// Reassembles node frames from notifications. Probe responses share the magic and CRC, so
+/// [`Reassembler::with_frame_length`] reassembles them too.
+///
+/// A notification that starts with the magic while a frame is still incomplete usually means the
+/// rest of that frame was lost, or another frame was started part way through it. The rest of a
+/// frame can start with the magic bytes too, though, so the incomplete frame is only dropped
+/// straight away when it's known to be cut short: its length is known and the notification can't
+/// be what completes it, having the wrong CRC or not starting another frame after the end.
+/// Otherwise the notification is held on to as both the rest of the frame and the start of a new
+/// one, and whichever passes its CRC is kept.
+///
+/// A notification that doesn't start with the magic while no frame is in progress is the rest of
+/// a frame whose start was lost, and is dropped. Frames that fail their CRC, having lost
+/// something in the middle, are dropped too.
+#[derive(Debug)]
+pub struct Reassembler {
+    frame_length: fn(&[u8]) -> Option<usize>,
+    partial: Vec<u8>,
+    /// Where in `partial` notifications that started with the magic were added, in case the
+    /// frame before turns out to have been cut short.
+    restarts: Vec<usize>,
+    dropped_frames: usize,
+    dropped_fragments: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::with_frame_length(framing::frame_length)
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// For frames with a different header, given how to find their length from their start, or
+    /// `None` if there isn't enough of it yet.
+    pub fn with_frame_length(frame_length: fn(&[u8]) -> Option<usize>) -> Self {
+        Self {
+            frame_length,
+            partial: Vec::new(),
+            restarts: Vec::new(),
+            dropped_frames: 0,
+            dropped_fragments: 0,
+        }
+    }
+
+    /// Returns each frame the notification completes.
+    pub fn push(&mut self, notification: &[u8]) -> Vec<Vec<u8>> {
+        if notification.starts_with(&MAGIC) && !self.partial.is_empty() {
+            if self.cannot_continue(notification) {
+                self.partial.clear();
+                self.restarts.clear();
+                self.dropped_frames += 1;
+            } else {
+                self.restarts.push(self.partial.len());
+            }
+        }
+        if self.partial.is_empty() && !is_frame_start(notification) {
+            self.dropped_fragments += 1;
+            return Vec::new();
+        }
+        self.partial.extend_from_slice(notification);
+
+        // Some senders pack the start of the next frame in after the end of the last.
+        let mut frames = Vec::new();
+        loop {
+            if let Some(length) = self.complete_frame(&self.partial) {
+                frames.push(self.partial[..length].to_vec());
+                self.discard(length);
+            } else if let Some(&start) = self
+                .restarts
+                .iter()
+                .find(|&&start| self.complete_frame(&self.partial[start..]).is_some())
+            {
+                // A frame started in a later notification is already complete, so the one
+                // before it was cut short.
+                self.dropped_frames += 1;
+                self.discard(start);
+                continue;
+            } else if let Some(length) =
+                (self.frame_length)(&self.partial).filter(|&length| length <= self.partial.len())
+            {
+                // Lost something in the middle, unless it was cut short by the next frame.
+                self.dropped_frames += 1;
+                self.discard(self.restarts.first().copied().unwrap_or(length));
+            } else {
+                break;
+            }
+            if !is_frame_start(&self.partial) {
+                self.partial.clear();
+                self.restarts.clear();
+                self.dropped_fragments += 1;
+            }
+        }
+        frames
+    }
+
+    /// Whether `notification`, starting with the magic, certainly isn't the rest of the frame in
+    /// progress.
+    fn cannot_continue(&self, notification: &[u8]) -> bool {
+        let Some(length) = (self.frame_length)(&self.partial) else {
+            return false;
+        };
+        let remaining = length.saturating_sub(self.partial.len());
+        if notification.len() < remaining {
+            return false;
+        }
+        let frame = [&self.partial, &notification[..remaining]].concat();
+        !crc_is_valid(&frame) || !is_frame_start(&notification[remaining..])
+    }
+
+    /// The length of the frame `bytes` starts with, if it's all there and its CRC is valid.
+    fn complete_frame(&self, bytes: &[u8]) -> Option<usize> {
+        (self.frame_length)(bytes)
+            .filter(|&length| bytes.len() >= length && crc_is_valid(&bytes[..length]))
+    }
+
+    /// Drop the first `length` bytes held.
+    fn discard(&mut self, length: usize) {
+        self.partial.drain(..length);
+        self.restarts.retain_mut(|start| {
+            *start = start.saturating_sub(length);
+            *start > 0
+        });
+    }
+
+    /// Forget any incomplete frame, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.restarts.clear();
+        if !self.partial.is_empty() {
+            self.partial.clear();
+            self.dropped_frames += 1;
+        }
+    }
+
+    /// Whether part of a frame has been received.
+    pub fn in_progress(&self) -> bool {
+        !self.partial.is_empty()
+    }
+
+    /// Frames that were started but never completed, or failed their CRC.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped_frames
+    }
+
+    /// Fragments that didn't belong to any frame.
+    pub fn dropped_fragments(&self) -> usize {
+        self.dropped_fragments
+    }
+}
+
+/// Whether `bytes` could be the start of a frame. Empty counts, so nothing is left over.
+fn is_frame_start(bytes: &[u8]) -> bool {
+    let length = bytes.len().min(MAGIC.len());
+    bytes[..length] == MAGIC[..length]
+}
+
+#[cfg(test)]
+fn test_frame(request_id: u32) -> Vec<u8> {
+    read_logs_frame(request_id, 0, 100)
+}
+
+#[cfg(test)]
+fn read_logs_frame(
+    request_id: u32,
+    sequence_number_start: u32,
+    sequence_number_end: u32,
+) -> Vec<u8> {
+    Request::new_with_id(
+        RequestMessage::ReadLogs(ReadLogs {
+            probe_serial_number: SerialNumber { number: 0x10001ded },
+            sequence_number_start,
+            sequence_number_end,
+        }),
+        request_id,
+    )
+    .to_bytes()
+    .unwrap()
+}
+
+#[test]
+fn test_segment_and_reassemble() {
+    let frame = test_frame(1);
+    assert_eq!(frame.len(), 22);
+
+    let fragments: Vec<_> = segment(&frame, DEFAULT_ATT_MTU).collect();
+    assert_eq!(fragments.len(), 2);
+    assert_eq!(fragments[0].len(), 20);
+
+    let mut reassembler = Reassembler::new();
+    assert_eq!(reassembler.push(fragments[0]), Vec::<Vec<u8>>::new());
+    assert!(reassembler.in_progress());
+    assert_eq!(reassembler.push(fragments[1]), vec![frame.clone()]);
+
+    // Two frames packed together, split awkwardly.
+    let packed = [frame.clone(), test_frame(2)].concat();
+    let mut frames = Vec::new();
+    for fragment in segment(&packed, 13) {
+        frames.extend(reassembler.push(fragment));
+    }
+    assert_eq!(frames, vec![frame, test_frame(2)]);
+    assert_eq!(reassembler.dropped_frames(), 0);
+    assert!(!reassembler.in_progress());
+}
+
+#[test]
+fn test_reassemble_with_lost_fragments() {
+    let first = test_frame(1);
+    let second = test_frame(2);
+    let third = test_frame(3);
+    let mut reassembler = Reassembler::new();
+
+    // The end of the first frame is lost, so the second starts before it's complete.
+    reassembler.push(&first[..8]);
+    reassembler.push(&second[..8]);
+    assert_eq!(reassembler.push(&second[8..]), vec![second.clone()]);
+    assert_eq!(reassembler.dropped_frames(), 1);
+
+    // The start of a frame is lost.
+    assert_eq!(reassembler.push(&third[8..]), Vec::<Vec<u8>>::new());
+    assert_eq!(reassembler.dropped_fragments(), 1);
+
+    // The middle of a frame is lost, so its CRC fails once it's long enough.
+    reassembler.push(&first[..8]);
+    assert_eq!(reassembler.push(&third[12..]), Vec::<Vec<u8>>::new());
+    assert_eq!(reassembler.push(&[0; 8]), Vec::<Vec<u8>>::new());
+    assert_eq!(reassembler.dropped_frames(), 2);
+
+    assert_eq!(reassembler.push(&third), vec![third]);
+}
+
+#[test]
+fn test_reassemble_continuation_starting_with_magic() {
+    // The top of each sequence number happens to be the magic, and the last fragment is the top
+    // of the end.
+    let frame = read_logs_frame(1, 0xfeca_0000, 0xfeca_0000);
+    let fragments: Vec<_> = segment(&frame, DEFAULT_ATT_MTU).collect();
+    assert_eq!(fragments[1], MAGIC);
+
+    let mut reassembler = Reassembler::new();
+    reassembler.push(fragments[0]);
+    assert_eq!(reassembler.push(fragments[1]), vec![frame.clone()]);
+
+    // Split so the continuation starting with the magic is too short to complete the frame.
+    reassembler.push(&frame[..16]);
+    assert_eq!(reassembler.push(&frame[16..20]), Vec::<Vec<u8>>::new());
+    assert_eq!(reassembler.push(&frame[20..]), vec![frame.clone()]);
+    assert_eq!(reassembler.dropped_frames(), 0);
+
+    // A new frame that really does cut one short is still picked up straight away.
+    let second = test_frame(2);
+    reassembler.push(&frame[..12]);
+    assert_eq!(reassembler.push(&second), vec![second]);
+    assert_eq!(reassembler.dropped_frames(), 1);
+    assert!(!reassembler.in_progress());
+}
@@ -6,10 +6,13 @@ use alloc::{format, vec::Vec};
 use crc::{Crc, CRC_16_IBM_3740};
 use deku::prelude::*;
 
-use crate::EncapsulatableMessage;
+use crate::{Color, EncapsulatableMessage};
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
-pub struct SetProbeId {}
+pub struct SetProbeId {
+    #[deku(bits = "3", pad_bits_before = "5")]
+    pub probe_id: u8,
+}
 impl EncapsulatableMessage for SetProbeId {
     type Encapsulation = Request;
     fn encapsulate(self) -> Self::Encapsulation {
@@ -18,7 +21,10 @@ impl EncapsulatableMessage for SetProbeId {
 }
 
 #[derive(Debug, PartialEq, DekuWrite, DekuRead)]
-pub struct SetProbeColor {}
+pub struct SetProbeColor {
+    #[deku(pad_bits_before = "5")]
+    pub color: Color,
+}
 impl EncapsulatableMessage for SetProbeColor {
     type Encapsulation = Request;
     fn encapsulate(self) -> Self::Encapsulation {
@@ -129,3 +135,17 @@ fn test_read_logs_request() {
         vec![0xca, 0xfe, 0x82, 0x13, 0x04, 0x08, 0x08, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00],
     )
 }
+
+#[test]
+fn test_set_probe_color_request() {
+    let request = SetProbeColor { color: Color::Grey }.encapsulate();
+
+    assert_eq!(
+        request.to_bytes().unwrap(),
+        vec![0xca, 0xfe, 0xec, 0x81, 0x02, 0x01, 0x01]
+    );
+    assert_eq!(
+        Request::try_from(request.to_bytes().unwrap().as_slice()).unwrap(),
+        request
+    );
+}
@@ -3,66 +3,120 @@ extern crate alloc;
 use alloc::format;
 #[cfg(test)]
 use alloc::vec;
-use alloc::{borrow::Cow, string::ToString};
-use deku::{
-    ctx::BitSize,
-    no_std_io::{Seek, Write},
-    prelude::*,
-};
+use alloc::vec::Vec;
+use crc::{Crc, CRC_16_IBM_3740};
+use deku::{ctx::BitSize, prelude::*};
 
-use crate::{parse_raw_temperature_data, Temperature};
+use crate::{parse_raw_temperature_data, write_raw_temperature_data, Temperature};
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct SetProbeId {}
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+pub struct SetProbeColor {}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct ReadSessionInformation {
-    probe_session_id: u32,
-    probe_sample_period: u16,
+    pub probe_session_id: u32,
+    pub probe_sample_period: u16,
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 pub struct ReadLogs {
     pub sequence_number: u32,
-    #[deku(reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))")]
+    #[deku(
+        reader = "parse_raw_temperature_data(deku::reader, BitSize(8*13))",
+        writer = "write_raw_temperature_data(deku::writer, &self.temperatures)"
+    )]
     pub temperatures: [Temperature; 8],
     pub virtual_sensors_and_state: [u8; 7],
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(ctx = "response_type: u8", id = "response_type")]
 pub enum ResponseMessage {
     #[deku(id = "0x01")]
     SetProbeId(SetProbeId),
+    #[deku(id = "0x02")]
+    SetProbeColor(SetProbeColor),
     #[deku(id = "0x03")]
     ReadSessionInformation(ReadSessionInformation),
     #[deku(id = "0x04")]
     ReadLogs(ReadLogs),
 }
 
-#[derive(Debug, PartialEq, DekuRead)]
+impl ResponseMessage {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DekuError> {
+        match self {
+            ResponseMessage::SetProbeId(r) => r.to_bytes(),
+            ResponseMessage::SetProbeColor(r) => r.to_bytes(),
+            ResponseMessage::ReadSessionInformation(r) => r.to_bytes(),
+            ResponseMessage::ReadLogs(r) => r.to_bytes(),
+        }
+    }
+}
+
+/// The magic, CRC, response type, success and payload length.
+const HEADER_LENGTH: usize = 7;
+
+/// The length of the response `bytes` starts with, once enough of the header is there to say.
+pub fn frame_length(bytes: &[u8]) -> Option<usize> {
+    let payload_length = *bytes.get(HEADER_LENGTH - 1)? as usize;
+    Some(HEADER_LENGTH + payload_length)
+}
+
+#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(magic = b"\xca\xfe")]
 pub struct Response {
     crc: u16,
     pub response_type: u8,
     #[deku(bytes = "1")]
-    success: bool,
+    pub success: bool,
     payload_length: u8,
     #[deku(ctx = "*response_type")]
     pub message: ResponseMessage,
 }
 
-impl DekuWriter for Response {
-    fn to_writer<W: Write + Seek>(&self, _: &mut Writer<W>, _: ()) -> Result<(), DekuError> {
-        Err(DekuError::Parse(Cow::from("Not implimented".to_string())))
+impl Response {
+    pub fn new(message: ResponseMessage, success: bool) -> Self {
+        let binding = Crc::<u16>::new(&CRC_16_IBM_3740);
+        let mut digest = binding.digest();
+
+        let response_type = message
+            .deku_id()
+            .expect("New message doesn't have Deku id.");
+        let message_bytes = message.to_bytes().unwrap();
+
+        // CRC of message type, success, payload length, and payload bytes.
+        digest.update(&[response_type]);
+        digest.update(&[success as u8]);
+        digest.update(&[message_bytes.len() as u8]);
+        digest.update(&message_bytes);
+
+        Self {
+            crc: digest.finalize(),
+            response_type,
+            success,
+            payload_length: message_bytes.len() as u8,
+            message,
+        }
     }
 }
 
 #[test]
 fn test_parse_read_session_information_response() {
     let data = vec![202, 254, 188, 168, 3, 1, 6, 188, 254, 245, 34, 136, 19];
-    let (_extra, _message) = Response::from_bytes((data.as_slice(), 0)).unwrap();
-    //TODO: Finish
+    let (_extra, message) = Response::from_bytes((data.as_slice(), 0)).unwrap();
+
+    let expected = Response::new(
+        ResponseMessage::ReadSessionInformation(ReadSessionInformation {
+            probe_session_id: 0x22f5febc,
+            probe_sample_period: 5000,
+        }),
+        true,
+    );
+    assert_eq!(message, expected);
+    assert_eq!(expected.to_bytes().unwrap(), data);
 }
 
 #[test]
@@ -79,4 +133,5 @@ fn test_parse_read_logs_response() {
         response,
         Response::from_bytes((data.as_slice(), 0)).unwrap().1
     );
+    assert_eq!(response.to_bytes().unwrap(), data);
 }
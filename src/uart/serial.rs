@@ -0,0 +1,358 @@
+//! Talking to a node over its USB serial port.
+//!
+//! Nodes show up as a serial port when plugged in to a computer, and disappear again when
+//! unplugged. [`SerialTransport`] reopens the port when that happens, so a client can carry on
+//! once the node is back without starting over.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+use core::time::Duration;
+use std::io::{self, Read, Write};
+use std::time::Instant;
+
+use serialport::SerialPort;
+
+use super::node::framing::FrameDecoder;
+use super::transport::Transport;
+
+#[cfg(test)]
+use super::client::NodeClient;
+#[cfg(test)]
+use super::node;
+#[cfg(test)]
+use crate::sim::node::test_node;
+#[cfg(test)]
+use crate::SerialNumber;
+#[cfg(test)]
+use deku::DekuContainerWrite;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+/// The baud rate nodes use.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// e.g. `/dev/ttyACM0` or `COM3`.
+    pub path: String,
+    pub baud_rate: u32,
+    /// How long each read waits for bytes before checking for a deadline.
+    pub read_timeout: Duration,
+    /// How long to wait between attempts to reopen the port after it's gone away.
+    pub reconnect_interval: Duration,
+    /// How long [`SerialTransport::receive_frame`] waits for a frame before giving up, or
+    /// `None` to wait for as long as it takes, including for the node to be plugged back in.
+    pub receive_timeout: Option<Duration>,
+}
+
+impl SerialConfig {
+    pub fn new(path: impl Into<String>, baud_rate: u32) -> Self {
+        Self {
+            path: path.into(),
+            baud_rate,
+            read_timeout: Duration::from_millis(100),
+            reconnect_interval: Duration::from_millis(500),
+            receive_timeout: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SerialError {
+    /// The port couldn't be opened, e.g. because the node isn't plugged in.
+    Open(serialport::Error),
+    Io(io::Error),
+    /// No frame arrived within the receive timeout.
+    TimedOut,
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialError::Open(error) => write!(f, "couldn't open serial port: {}", error),
+            SerialError::Io(error) => write!(f, "serial port error: {}", error),
+            SerialError::TimedOut => write!(f, "timed out waiting for a frame"),
+        }
+    }
+}
+
+impl std::error::Error for SerialError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SerialError::Open(error) => Some(error),
+            SerialError::Io(error) => Some(error),
+            SerialError::TimedOut => None,
+        }
+    }
+}
+
+/// A [`Transport`] over a serial port.
+///
+/// Any error reading or writing is taken to mean the node has been unplugged. The port is
+/// closed, along with any partly received frame, and reopened by path on the next send or
+/// receive. Receiving keeps trying every `reconnect_interval` until its timeout; sending tries
+/// once more after reopening and otherwise returns the error.
+pub struct SerialTransport {
+    config: SerialConfig,
+    port: Option<Box<dyn SerialPort>>,
+    decoder: FrameDecoder,
+    /// Whether the port went away, rather than never having been opened or being closed on
+    /// purpose.
+    lost: bool,
+    reconnects: usize,
+}
+
+impl SerialTransport {
+    /// Open the port now, so a wrong path is found straight away.
+    pub fn open(config: SerialConfig) -> Result<Self, SerialError> {
+        let mut transport = Self::new(config);
+        transport.connect()?;
+        Ok(transport)
+    }
+
+    /// Don't open the port until it's first used.
+    pub fn new(config: SerialConfig) -> Self {
+        Self {
+            config,
+            port: None,
+            decoder: FrameDecoder::new(),
+            lost: false,
+            reconnects: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SerialConfig {
+        &self.config
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.port.is_some()
+    }
+
+    /// How many times the port has been reopened after going away.
+    pub fn reconnects(&self) -> usize {
+        self.reconnects
+    }
+
+    /// Close the port. It's reopened when next used.
+    pub fn disconnect(&mut self) {
+        self.port = None;
+        self.decoder = FrameDecoder::new();
+        self.lost = false;
+    }
+
+    fn lose_port(&mut self) {
+        self.disconnect();
+        self.lost = true;
+    }
+
+    fn connect(&mut self) -> Result<&mut Box<dyn SerialPort>, SerialError> {
+        if self.port.is_none() {
+            let port = serialport::new(&self.config.path, self.config.baud_rate)
+                .timeout(self.config.read_timeout)
+                .open()
+                .map_err(SerialError::Open)?;
+            self.port = Some(port);
+            if self.lost {
+                self.lost = false;
+                self.reconnects += 1;
+            }
+        }
+        Ok(self.port.as_mut().unwrap())
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), SerialError> {
+        if write_frame(self.connect()?, frame).is_ok() {
+            return Ok(());
+        }
+        self.lose_port();
+        write_frame(self.connect()?, frame).map_err(|error| {
+            self.lose_port();
+            SerialError::Io(error)
+        })
+    }
+
+    /// Read whatever has arrived, if anything, noting if the port has gone away.
+    fn read(&mut self) {
+        let Some(port) = self.port.as_mut() else {
+            return;
+        };
+        let mut buffer = [0; 256];
+        match port.read(&mut buffer) {
+            Ok(0) => self.lose_port(),
+            Ok(length) => self.decoder.push(&buffer[..length]),
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+                ) => {}
+            Err(_) => self.lose_port(),
+        }
+    }
+}
+
+fn write_frame(port: &mut Box<dyn SerialPort>, frame: &[u8]) -> io::Result<()> {
+    port.write_all(frame)?;
+    port.flush()
+}
+
+impl Transport for SerialTransport {
+    type Error = SerialError;
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), SerialError> {
+        self.write(frame)
+    }
+
+    fn receive_frame(&mut self) -> Result<Vec<u8>, SerialError> {
+        let deadline = self
+            .config
+            .receive_timeout
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(frame);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(SerialError::TimedOut);
+            }
+            if self.connect().is_err() {
+                let wait = deadline.map_or(self.config.reconnect_interval, |deadline| {
+                    self.config
+                        .reconnect_interval
+                        .min(deadline.saturating_duration_since(Instant::now()))
+                });
+                std::thread::sleep(wait);
+                continue;
+            }
+            self.read();
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_config(path: &str) -> SerialConfig {
+    SerialConfig {
+        read_timeout: Duration::from_millis(20),
+        reconnect_interval: Duration::from_millis(20),
+        receive_timeout: Some(Duration::from_millis(500)),
+        ..SerialConfig::new(path, DEFAULT_BAUD_RATE)
+    }
+}
+
+/// Pass what the gateway writes to the node until it has something to say, and write that back.
+#[cfg(test)]
+fn answer<S: crate::sim::probe::TemperatureSource>(
+    node: &mut crate::sim::node::NodeEmulator<S>,
+    port: &mut serialport::TTYPort,
+) {
+    let mut buffer = [0; 256];
+    let mut frame = node.poll_transmit();
+    while frame.is_none() {
+        let length = port.read(&mut buffer).unwrap();
+        node.handle_input(&buffer[..length]);
+        frame = node.poll_transmit();
+    }
+    while let Some(bytes) = frame {
+        port.write_all(&bytes).unwrap();
+        frame = node.poll_transmit();
+    }
+}
+
+#[cfg(test)]
+fn read_session_information() -> node::request::RequestMessage {
+    node::request::RequestMessage::ReadSessionInformation(node::request::ReadSessionInformation {
+        serial_number: SerialNumber { number: 0x10001ded },
+    })
+}
+
+#[cfg(test)]
+fn session_id(response: node::response::Response) -> u32 {
+    match response.message {
+        node::response::ResponseMessage::ReadSessionInformation(message) => {
+            message.probe_session_id
+        }
+        message => panic!("Unexpected response {message:?}"),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serial_transport_over_pty() {
+    let (mut node_port, gateway_port) = serialport::TTYPort::pair().unwrap();
+    node_port.set_timeout(Duration::from_secs(5)).unwrap();
+    let path = gateway_port.name().unwrap();
+    let mut client = NodeClient::new(SerialTransport::open(test_config(&path)).unwrap());
+    let mut node = test_node();
+
+    let request_id = client.send(read_session_information()).unwrap();
+    answer(&mut node, &mut node_port);
+    assert_eq!(
+        session_id(client.receive_response(request_id).unwrap()),
+        0x22f5febc
+    );
+
+    // Nothing more is coming.
+    assert!(matches!(
+        client.receive_response(request_id),
+        Err(super::client::ClientError::Transport(SerialError::TimedOut))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_serial_transport_reconnects() {
+    // A link standing in for the device path, pointed at a new terminal when it's "plugged back
+    // in", much as udev would.
+    let directory =
+        std::env::temp_dir().join(std::format!("meatnet-serial-test-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let link = directory.join("node");
+    let plug_in = || {
+        let (mut node_port, gateway_port) = serialport::TTYPort::pair().unwrap();
+        node_port.set_timeout(Duration::from_secs(5)).unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(gateway_port.name().unwrap(), &link).unwrap();
+        (node_port, gateway_port)
+    };
+
+    let (node_port, gateway_port) = plug_in();
+    let mut transport = SerialTransport::open(test_config(link.to_str().unwrap())).unwrap();
+    // Half a frame arrives before it's unplugged.
+    let frame = node::request::Request::new(read_session_information())
+        .to_bytes()
+        .unwrap();
+    let mut node_port = node_port;
+    node_port.write_all(&frame[..6]).unwrap();
+    drop((node_port, gateway_port));
+
+    let (mut node_port, _gateway_port) = plug_in();
+    node_port.write_all(&frame).unwrap();
+    assert_eq!(transport.receive_frame().unwrap(), frame);
+    assert_eq!(transport.reconnects(), 1);
+
+    let mut client = NodeClient::new(transport);
+    let mut node = test_node();
+    let request_id = client.send(read_session_information()).unwrap();
+    answer(&mut node, &mut node_port);
+    assert_eq!(
+        session_id(client.receive_response(request_id).unwrap()),
+        0x22f5febc
+    );
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_serial_transport_times_out_while_reconnecting() {
+    let mut transport = SerialTransport::new(SerialConfig {
+        reconnect_interval: Duration::from_secs(10),
+        receive_timeout: Some(Duration::from_millis(50)),
+        ..SerialConfig::new("/nonexistent/meatnet-node", DEFAULT_BAUD_RATE)
+    });
+    let started = Instant::now();
+    assert!(matches!(
+        transport.receive_frame(),
+        Err(SerialError::TimedOut)
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
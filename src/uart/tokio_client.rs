@@ -0,0 +1,385 @@
+//! A tokio client for a node, for gateways already running on tokio.
+//!
+//! [`TokioNodeClient`] owns the link to the node, e.g. a serial port or a TCP connection to one,
+//! and spawns a task reading from it. Responses are handed to whichever request is waiting for
+//! them, so requests can be made concurrently from a shared client, and the probe status messages
+//! and heartbeats the node sends by itself are broadcast to every
+//! [`TokioNodeClient::unsolicited`] stream.
+//!
+//! This doesn't build on [`AsyncNodeClient`](super::client::AsyncNodeClient), which only reads
+//! from its transport while a caller is awaiting a response through `&mut self`. That suits a
+//! single task talking to a node, but a gateway wants one client shared between tasks with
+//! several requests in flight, and status messages as they arrive rather than the next time it
+//! makes a request. So here a task reads all the time and routes each frame as it comes in.
+//! Errors are the same [`ClientError`], with [`LinkError`] for what went wrong with the link.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::time::Duration;
+use deku::prelude::*;
+use std::io;
+use std::sync::Mutex;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::BroadcastStream;
+
+use super::client::ClientError;
+use super::node::framing::FrameDecoder;
+use super::node::request::{self, HeartbeatMessage, ProbeStatusMessage, RequestMessage};
+use super::node::response::{self, Response, ResponseMessage};
+use super::node::{try_request_or_response_from, MessageType};
+use crate::{Color, SerialNumber};
+
+#[cfg(test)]
+use crate::sim::node::test_node;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+#[cfg(test)]
+use tokio_stream::StreamExt;
+
+/// How long to wait for a response before giving up on it.
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Unsolicited messages kept for each stream that's fallen behind before the oldest are dropped.
+const UNSOLICITED_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+pub enum LinkError {
+    Io(io::Error),
+    /// The link was closed, or failed, before the response arrived.
+    Closed,
+    TimedOut,
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Io(error) => write!(f, "link error: {}", error),
+            LinkError::Closed => write!(f, "the link is closed"),
+            LinkError::TimedOut => write!(f, "timed out waiting for a response"),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LinkError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+pub type TokioClientError = ClientError<LinkError>;
+
+/// A message the node sent by itself rather than in response to a request.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Unsolicited {
+    ProbeStatus(ProbeStatusMessage),
+    Heartbeat(HeartbeatMessage),
+}
+
+/// Senders for the responses to each request still waiting, by request ID, or `None` once the
+/// link has closed.
+type Pending = Arc<Mutex<Option<BTreeMap<u32, mpsc::UnboundedSender<Response>>>>>;
+
+/// The responses to one request. It stops waiting for them when dropped.
+struct Responses {
+    request_id: u32,
+    receiver: mpsc::UnboundedReceiver<Response>,
+    pending: Pending,
+}
+
+impl Responses {
+    async fn next(&mut self, timeout: Duration) -> Result<Response, TokioClientError> {
+        match tokio::time::timeout(timeout, self.receiver.recv()).await {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(ClientError::Transport(LinkError::Closed)),
+            Err(_) => Err(ClientError::Transport(LinkError::TimedOut)),
+        }
+    }
+}
+
+impl Drop for Responses {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.request_id);
+        }
+    }
+}
+
+pub struct TokioNodeClient<L> {
+    writer: tokio::sync::Mutex<WriteHalf<L>>,
+    pending: Pending,
+    /// Kept only to subscribe new streams, so they end when the reader does.
+    unsolicited: broadcast::Receiver<Unsolicited>,
+    reader: JoinHandle<()>,
+    response_timeout: Duration,
+}
+
+impl<L: AsyncRead + AsyncWrite + Send + 'static> TokioNodeClient<L> {
+    /// Take over `link` and start reading from it. Must be called within a tokio runtime.
+    pub fn new(link: L) -> Self {
+        let (reader, writer) = tokio::io::split(link);
+        let pending: Pending = Arc::new(Mutex::new(Some(BTreeMap::new())));
+        let (sender, unsolicited) = broadcast::channel(UNSOLICITED_CAPACITY);
+        let reader = tokio::spawn(read_frames(reader, Arc::clone(&pending), sender));
+        Self {
+            writer: tokio::sync::Mutex::new(writer),
+            pending,
+            unsolicited,
+            reader,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+        }
+    }
+
+    pub fn set_response_timeout(&mut self, timeout: Duration) {
+        self.response_timeout = timeout;
+    }
+
+    /// Whether the link is still open.
+    pub fn is_connected(&self) -> bool {
+        !self.reader.is_finished()
+    }
+
+    /// Probe status messages and heartbeats from now on. The stream ends when the link closes,
+    /// and yields an error in place of any messages dropped for being read too slowly.
+    pub fn unsolicited(&self) -> BroadcastStream<Unsolicited> {
+        BroadcastStream::new(self.subscribe())
+    }
+
+    /// The same as [`TokioNodeClient::unsolicited`], as a plain receiver.
+    pub fn subscribe(&self) -> broadcast::Receiver<Unsolicited> {
+        self.unsolicited.resubscribe()
+    }
+
+    async fn send(&self, message: RequestMessage) -> Result<Responses, TokioClientError> {
+        let request = request::Request::new(message);
+        let request_id = request.request_header.request_id;
+        let bytes = request.to_bytes().map_err(ClientError::Encode)?;
+
+        // Listen before sending so a quick response can't be missed.
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or(ClientError::Transport(LinkError::Closed))?
+            .insert(request_id, sender);
+        let responses = Responses {
+            request_id,
+            receiver,
+            pending: Arc::clone(&self.pending),
+        };
+
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(&bytes)
+            .await
+            .map_err(|error| ClientError::Transport(LinkError::Io(error)))?;
+        writer
+            .flush()
+            .await
+            .map_err(|error| ClientError::Transport(LinkError::Io(error)))?;
+        Ok(responses)
+    }
+
+    /// Send a request and wait for its first response.
+    pub async fn request(&self, message: RequestMessage) -> Result<Response, TokioClientError> {
+        self.send(message).await?.next(self.response_timeout).await
+    }
+
+    pub async fn read_session_information(
+        &self,
+        serial_number: SerialNumber,
+    ) -> Result<response::ReadSessionInformation, TokioClientError> {
+        let response = self
+            .request(RequestMessage::ReadSessionInformation(
+                request::ReadSessionInformation { serial_number },
+            ))
+            .await?;
+        match successful(response)? {
+            ResponseMessage::ReadSessionInformation(information) => Ok(information),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    /// Read the logs with sequence numbers in `range`.
+    ///
+    /// The node sends a response for each log it has, in order, and nothing for those it
+    /// doesn't, so this returns once the last log in the range arrives or the node goes quiet
+    /// for the response timeout. It's only an error if nothing arrives at all.
+    pub async fn read_logs(
+        &self,
+        serial_number: SerialNumber,
+        range: RangeInclusive<u32>,
+    ) -> Result<Vec<response::ReadLogs>, TokioClientError> {
+        let mut responses = self
+            .send(RequestMessage::ReadLogs(request::ReadLogs {
+                probe_serial_number: serial_number,
+                sequence_number_start: *range.start(),
+                sequence_number_end: *range.end(),
+            }))
+            .await?;
+        let mut logs = Vec::new();
+        loop {
+            let response = match responses.next(self.response_timeout).await {
+                Ok(response) => response,
+                Err(ClientError::Transport(LinkError::TimedOut)) if !logs.is_empty() => {
+                    return Ok(logs)
+                }
+                Err(error) => return Err(error),
+            };
+            let ResponseMessage::ReadLogs(log) = successful(response)? else {
+                return Err(ClientError::UnexpectedResponse);
+            };
+            let last = log.sequence_number >= *range.end();
+            logs.push(log);
+            if last {
+                return Ok(logs);
+            }
+        }
+    }
+
+    pub async fn set_probe_id(
+        &self,
+        serial_number: SerialNumber,
+        probe_id: u8,
+    ) -> Result<(), TokioClientError> {
+        let response = self
+            .request(RequestMessage::SetProbeId(request::SetProbeId {
+                probe_serial_number: serial_number,
+                probe_id,
+            }))
+            .await?;
+        match successful(response)? {
+            ResponseMessage::SetProbeId => Ok(()),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn set_probe_color(
+        &self,
+        serial_number: SerialNumber,
+        color: Color,
+    ) -> Result<(), TokioClientError> {
+        let response = self
+            .request(RequestMessage::SetProbeColor(request::SetProbeColor {
+                probe_serial_number: serial_number,
+                color,
+            }))
+            .await?;
+        match successful(response)? {
+            ResponseMessage::SetProbeColor => Ok(()),
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+}
+
+impl<L> Drop for TokioNodeClient<L> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+fn successful(response: Response) -> Result<ResponseMessage, TokioClientError> {
+    if response.header.success {
+        Ok(response.message)
+    } else {
+        Err(ClientError::Unsuccessful)
+    }
+}
+
+async fn read_frames<L: AsyncRead>(
+    mut reader: ReadHalf<L>,
+    pending: Pending,
+    unsolicited: broadcast::Sender<Unsolicited>,
+) {
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = [0; 256];
+    while let Ok(length @ 1..) = reader.read(&mut buffer).await {
+        decoder.push(&buffer[..length]);
+        while let Some(frame) = decoder.next_frame() {
+            match try_request_or_response_from(&frame) {
+                Ok(MessageType::Response(response)) => {
+                    let pending = pending.lock().unwrap();
+                    if let Some(sender) = pending
+                        .as_ref()
+                        .and_then(|pending| pending.get(&response.header.request_id))
+                    {
+                        let _ = sender.send(response);
+                    }
+                }
+                Ok(MessageType::Request(request)) => {
+                    let message = match request.message {
+                        RequestMessage::ProbeStatusMessage(message) => {
+                            Unsolicited::ProbeStatus(message)
+                        }
+                        RequestMessage::HeartbeatMessage(message) => {
+                            Unsolicited::Heartbeat(message)
+                        }
+                        _ => continue,
+                    };
+                    // Nobody listening is fine.
+                    let _ = unsolicited.send(message);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    // Dropping the senders wakes everything still waiting.
+    pending.lock().unwrap().take();
+}
+
+#[test]
+fn test_tokio_node_client() {
+    let serial_number = SerialNumber { number: 0x10001ded };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let (link, mut node_link) = tokio::io::duplex(1024);
+        let client = TokioNodeClient::new(link);
+        let mut unsolicited = client.unsolicited();
+
+        // The node has status messages and heartbeats waiting before any requests arrive.
+        tokio::spawn(async move {
+            let mut node = test_node();
+            node.handle_timeout(Duration::from_secs(20));
+            let mut buffer = [0; 256];
+            loop {
+                while let Some(frame) = node.poll_transmit() {
+                    node_link.write_all(&frame).await.unwrap();
+                }
+                match node_link.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(length) => node.handle_input(&buffer[..length]),
+                }
+            }
+        });
+
+        let information = client.read_session_information(serial_number).await.unwrap();
+        assert_eq!(information.probe_session_id, 0x22f5febc);
+
+        let logs = client.read_logs(serial_number, 2..=3).await.unwrap();
+        let sequence_numbers: Vec<_> = logs.iter().map(|log| log.sequence_number).collect();
+        assert_eq!(sequence_numbers, [2, 3]);
+
+        assert!(matches!(
+            client
+                .set_probe_color(SerialNumber { number: 1 }, Color::Grey)
+                .await,
+            Err(ClientError::Unsuccessful)
+        ));
+        client.set_probe_id(serial_number, 3).await.unwrap();
+
+        assert!(matches!(
+            unsolicited.next().await,
+            Some(Ok(Unsolicited::ProbeStatus(message))) if message.probe_serial_number == serial_number
+        ));
+    });
+}
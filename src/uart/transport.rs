@@ -0,0 +1,253 @@
+//! Moving whole frames to and from a device, whatever the link.
+//!
+//! A transport deals in complete frames: anything splitting them up on the way, like a serial
+//! line or BLE notifications, reassembles them before they're returned. [`Transport`] blocks
+//! and [`AsyncTransport`] doesn't; a link can implement either or both.
+
+extern crate alloc;
+
+use alloc::{collections::VecDeque, rc::Rc, vec::Vec};
+use core::cell::RefCell;
+use core::fmt;
+use core::future::{poll_fn, Future};
+use core::task::{Poll, Waker};
+
+#[cfg(test)]
+use alloc::vec;
+#[cfg(test)]
+use pretty_assertions::assert_eq;
+
+pub trait Transport {
+    type Error;
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Waits for the next frame.
+    fn receive_frame(&mut self) -> Result<Vec<u8>, Self::Error>;
+}
+
+pub trait AsyncTransport {
+    type Error;
+
+    fn send_frame(&mut self, frame: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn receive_frame(&mut self) -> impl Future<Output = Result<Vec<u8>, Self::Error>>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LoopbackError {
+    /// Nothing has been sent to this end. Everything happens on one thread, so blocking would
+    /// wait forever.
+    Empty,
+    /// The other end has been dropped.
+    Closed,
+}
+
+impl fmt::Display for LoopbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopbackError::Empty => write!(f, "no frames to receive"),
+            LoopbackError::Closed => write!(f, "the other end is closed"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    frames: VecDeque<Vec<u8>>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// One end of an in-memory link. Frames sent on one end are received on the other, in order.
+#[derive(Debug)]
+pub struct Loopback {
+    incoming: Rc<RefCell<Queue>>,
+    outgoing: Rc<RefCell<Queue>>,
+}
+
+impl Loopback {
+    pub fn pair() -> (Loopback, Loopback) {
+        let (a, b) = (Rc::default(), Rc::default());
+        (
+            Loopback {
+                incoming: Rc::clone(&a),
+                outgoing: Rc::clone(&b),
+            },
+            Loopback {
+                incoming: b,
+                outgoing: a,
+            },
+        )
+    }
+
+    /// Frames waiting to be received on this end.
+    pub fn pending(&self) -> usize {
+        self.incoming.borrow().frames.len()
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<(), LoopbackError> {
+        if Rc::strong_count(&self.outgoing) < 2 {
+            return Err(LoopbackError::Closed);
+        }
+        let mut outgoing = self.outgoing.borrow_mut();
+        outgoing.frames.push_back(frame.to_vec());
+        if let Some(waker) = outgoing.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn try_receive(&self) -> Result<Vec<u8>, LoopbackError> {
+        let mut incoming = self.incoming.borrow_mut();
+        match incoming.frames.pop_front() {
+            Some(frame) => Ok(frame),
+            None if incoming.closed => Err(LoopbackError::Closed),
+            None => Err(LoopbackError::Empty),
+        }
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        let mut outgoing = self.outgoing.borrow_mut();
+        outgoing.closed = true;
+        if let Some(waker) = outgoing.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Transport for Loopback {
+    type Error = LoopbackError;
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), LoopbackError> {
+        self.send(frame)
+    }
+
+    fn receive_frame(&mut self) -> Result<Vec<u8>, LoopbackError> {
+        self.try_receive()
+    }
+}
+
+impl AsyncTransport for Loopback {
+    type Error = LoopbackError;
+
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), LoopbackError> {
+        self.send(frame)
+    }
+
+    async fn receive_frame(&mut self) -> Result<Vec<u8>, LoopbackError> {
+        poll_fn(|context| match self.try_receive() {
+            Err(LoopbackError::Empty) => {
+                self.incoming.borrow_mut().waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        })
+        .await
+    }
+}
+
+/// A transport that answers each frame sent with whatever `respond` returns, e.g. the output of
+/// a [`NodeEmulator`](crate::sim::node::NodeEmulator) or canned responses.
+pub struct MockTransport<F> {
+    respond: F,
+    received: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl<F: FnMut(&[u8]) -> Vec<Vec<u8>>> MockTransport<F> {
+    pub fn new(respond: F) -> Self {
+        Self {
+            respond,
+            received: VecDeque::new(),
+            sent: Vec::new(),
+        }
+    }
+
+    /// Every frame sent so far.
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    /// Queue a frame to be received without anything being sent, e.g. an unsolicited message.
+    pub fn push_received(&mut self, frame: Vec<u8>) {
+        self.received.push_back(frame);
+    }
+
+    fn send(&mut self, frame: &[u8]) {
+        self.sent.push(frame.to_vec());
+        let responses = (self.respond)(frame);
+        self.received.extend(responses);
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Vec<Vec<u8>>> Transport for MockTransport<F> {
+    type Error = LoopbackError;
+
+    fn send_frame(&mut self, frame: &[u8]) -> Result<(), LoopbackError> {
+        self.send(frame);
+        Ok(())
+    }
+
+    fn receive_frame(&mut self) -> Result<Vec<u8>, LoopbackError> {
+        self.received.pop_front().ok_or(LoopbackError::Empty)
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Vec<Vec<u8>>> AsyncTransport for MockTransport<F> {
+    type Error = LoopbackError;
+
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), LoopbackError> {
+        self.send(frame);
+        Ok(())
+    }
+
+    async fn receive_frame(&mut self) -> Result<Vec<u8>, LoopbackError> {
+        self.received.pop_front().ok_or(LoopbackError::Empty)
+    }
+}
+
+/// Run a future to completion on this thread, for tests of async code without a runtime.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let mut context = core::task::Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn test_loopback() {
+    let (mut a, mut b) = Loopback::pair();
+    Transport::send_frame(&mut a, &[1, 2]).unwrap();
+    Transport::send_frame(&mut a, &[3]).unwrap();
+    assert_eq!(b.pending(), 2);
+    assert_eq!(Transport::receive_frame(&mut b), Ok(vec![1, 2]));
+    assert_eq!(block_on(AsyncTransport::receive_frame(&mut b)), Ok(vec![3]));
+    assert_eq!(Transport::receive_frame(&mut b), Err(LoopbackError::Empty));
+
+    drop(a);
+    assert_eq!(Transport::receive_frame(&mut b), Err(LoopbackError::Closed));
+    assert_eq!(
+        Transport::send_frame(&mut b, &[4]),
+        Err(LoopbackError::Closed)
+    );
+}
+
+#[test]
+fn test_mock_transport() {
+    let mut transport = MockTransport::new(|frame: &[u8]| vec![frame.to_vec(), vec![0]]);
+    Transport::send_frame(&mut transport, &[7]).unwrap();
+    assert_eq!(transport.sent(), [vec![7]]);
+    assert_eq!(Transport::receive_frame(&mut transport), Ok(vec![7]));
+    assert_eq!(Transport::receive_frame(&mut transport), Ok(vec![0]));
+    assert_eq!(
+        Transport::receive_frame(&mut transport),
+        Err(LoopbackError::Empty)
+    );
+}